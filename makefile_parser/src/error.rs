@@ -0,0 +1,22 @@
+use std::fmt;
+
+/// 严格模式下的解析错误
+#[derive(Debug)]
+pub struct ParseError {
+    /// 缺失的必需变量
+    pub missing: Vec<String>,
+    /// 无法识别或格式错误的行
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "missing required variables: {}", self.missing.join(", "))?;
+        if !self.warnings.is_empty() {
+            write!(f, " ({} warnings)", self.warnings.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -1,17 +1,41 @@
+mod error;
 mod model;
 
-use crate::model::MakefileConfig;
+pub use crate::error::ParseError;
+pub use crate::model::MakefileConfig;
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tracing::debug;
 
 /// 展开多行续行
-fn unfold_multiline(lines: &[String]) -> Vec<String> {
+///
+/// 续行中穿插的注释行会被跳过；注释行之后若是新的赋值语句，则视为续行已结束。
+/// 以 `\` 结尾的行会先截掉行内注释，如 `main.c # entry \`，转义的 `\#` 留给 `parse` 处理
+fn unfold_multiline(lines: &[String], re_assign: &Regex) -> Vec<String> {
     let mut result = Vec::new();
     let mut current = String::new();
+    let mut after_comment = false;
     for l in lines {
         let trimmed = l.trim_end();
-        if trimmed.ends_with('\\') {
-            current.push_str(&trimmed[..trimmed.len() - 1]);
+        if !current.is_empty() {
+            if trimmed.trim_start().starts_with('#') {
+                after_comment = true;
+                continue;
+            }
+            if after_comment && re_assign.is_match(trimmed.trim_start()) {
+                result.push(current.trim_end().to_string());
+                current.clear();
+            }
+        }
+        after_comment = false;
+        if let Some(stripped) = trimmed.strip_suffix('\\') {
+            if stripped.starts_with('\t') || current.starts_with('\t') {
+                current.push_str(stripped);
+            } else {
+                let end = comment_start(stripped).unwrap_or(stripped.len());
+                current.push_str(&stripped[..end]);
+            }
             current.push(' ');
         } else {
             current.push_str(trimmed);
@@ -25,90 +49,760 @@ fn unfold_multiline(lines: &[String]) -> Vec<String> {
     result
 }
 
-/// 解析 Makefile
+/// 从编译参数中提取 CPU/FPU/浮点 ABI
+fn extract_arch_flags(cfg: &mut MakefileConfig, val: &str) {
+    for token in val.split_whitespace() {
+        if let Some(cpu) = token.strip_prefix("-mcpu=") {
+            cfg.cpu = Some(cpu.to_string());
+        } else if let Some(fpu) = token.strip_prefix("-mfpu=") {
+            cfg.fpu = Some(fpu.to_string());
+        } else if let Some(float_abi) = token.strip_prefix("-mfloat-abi=") {
+            cfg.float_abi = Some(float_abi.to_string());
+        }
+    }
+}
+
+/// 是否为 -g、-g3、-gdwarf-2 等生成调试信息的参数
+fn is_debug_flag(token: &str) -> bool {
+    match token.strip_prefix("-g") {
+        Some("") => true,
+        Some(rest) => rest.starts_with("dwarf") || rest.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// 解析 `DEBUG = 1`/`DEBUG = 0`
+fn parse_debug(val: &str) -> Option<bool> {
+    match val.trim() {
+        "1" => Some(true),
+        "0" => Some(false),
+        other => {
+            debug!("Unknown DEBUG value `{}` in Makefile", other);
+            None
+        }
+    }
+}
+
+/// 变量引用的最大展开层数，避免循环引用
+const MAX_EXPAND_DEPTH: usize = 8;
+
+/// 展开 `$(NAME)`/`${NAME}` 引用，无法解析的引用保持原样
+fn expand_variables(
+    val: &str,
+    vars: &HashMap<String, String>,
+    re_ref: &Regex,
+    warnings: &mut Vec<String>,
+) -> String {
+    let mut result = val.to_string();
+    for _ in 0..MAX_EXPAND_DEPTH {
+        let expanded = re_ref
+            .replace_all(&result, |caps: &regex::Captures| match vars.get(&caps[1]) {
+                Some(v) => v.clone(),
+                None => caps[0].to_string(),
+            })
+            .to_string();
+        if expanded == result {
+            break;
+        }
+        result = expanded;
+    }
+    for cap in re_ref.captures_iter(&result) {
+        let reference = cap[0].to_string();
+        debug!("Unresolved reference {} in Makefile", reference);
+        warnings.push(format!("unresolved reference `{reference}`"));
+    }
+    result
+}
+
+/// Makefile 的必需变量
+const REQUIRED_VARIABLES: [&str; 2] = ["TARGET", "C_SOURCES"];
+
+/// 行内 `#` 注释的起始位置，引号内和转义的 `\#` 不算注释
+fn comment_start(line: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|&(_, next)| next == '#') => {
+                chars.next();
+            }
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            c if quote == Some(c) => quote = None,
+            '#' if quote.is_none() => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// 去除行内 `#` 注释，引号内和转义的 `\#` 保留为普通字符
+fn strip_inline_comment(line: &str) -> String {
+    let end = comment_start(line).unwrap_or(line.len());
+    line[..end].replace("\\#", "#")
+}
+
+/// 按空白拆分参数，引号内的空白不拆分，引号和 `\` 转义原样保留，
+/// 如 `-DVERSION="1.0 beta"`、`-DNAME='\"foo\"'`
+pub fn split_args(val: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut chars = val.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                current.push(c);
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            '"' | '\'' if quote.is_none() => {
+                quote = Some(c);
+                current.push(c);
+            }
+            c if quote == Some(c) => {
+                quote = None;
+                current.push(c);
+            }
+            c if c.is_whitespace() && quote.is_none() => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+/// 规范化相对路径，用于去重：统一为 `/` 分隔，去除 `./`，折叠 `dir/..`
+fn normalize_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let absolute = path.starts_with('/');
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." if parts.last().is_some_and(|p| *p != "..") => {
+                parts.pop();
+            }
+            ".." if absolute => {}
+            part => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    match (absolute, joined.is_empty()) {
+        (true, _) => format!("/{joined}"),
+        (false, true) => ".".to_string(),
+        (false, false) => joined,
+    }
+}
+
+/// 提取 `-I` 参数中的头文件路径，规范化后去重
+fn parse_includes(val: &str) -> Vec<String> {
+    let mut includes = Vec::new();
+    for token in split_args(val) {
+        if let Some(path) = token.strip_prefix("-I") {
+            // 路径两侧的引号只用于包住空格，不属于路径本身
+            let path = normalize_path(path.trim_matches(|c| c == '"' || c == '\''));
+            if !includes.contains(&path) {
+                includes.push(path);
+            }
+        }
+    }
+    includes
+}
+
+/// 提取 `-D` 参数中的宏定义并去重，如 `USE_HAL_DRIVER`、`HSE_VALUE=8000000`
+///
+/// 同时支持 `-DNAME` 与 `-D NAME`，`-include foo.h` 等其他参数会被跳过
+fn parse_defines(val: &str) -> Vec<String> {
+    let mut defines = Vec::new();
+    let mut tokens = split_args(val).into_iter();
+    while let Some(token) = tokens.next() {
+        let name = match token.as_str() {
+            "-D" => tokens.next(),
+            "-include" => {
+                tokens.next();
+                None
+            }
+            _ => token.strip_prefix("-D").map(str::to_string),
+        };
+        if let Some(name) = name
+            && !name.is_empty()
+            && !defines.contains(&name)
+        {
+            defines.push(name);
+        }
+    }
+    defines
+}
+
+/// 不是赋值语句时，判断是否为规则、命令或条件等合法语句
+fn is_rule_or_directive(line: &str) -> bool {
+    const DIRECTIVES: [&str; 15] = [
+        "ifeq", "ifneq", "ifdef", "ifndef", "else", "endif", "include", "-include", "sinclude",
+        "vpath", "export", "unexport", "override", "define", "endef",
+    ];
+    if line.starts_with('\t') {
+        return true;
+    }
+    let line = line.trim();
+    let first = line.split_whitespace().next().unwrap_or_default();
+    DIRECTIVES.contains(&first) || line.contains(':')
+}
+
+/// 解析 Makefile，无法识别的变量、格式错误的行和无法解析的引用会记录在 `warnings` 中
 pub fn parse_makefile(content: &str) -> MakefileConfig {
+    parse(content).0
+}
+
+/// 严格模式解析 Makefile
+///
+/// 缺少必需变量（TARGET、C_SOURCES）时返回错误，无法识别的变量和格式错误的行会记录在警告中
+pub fn parse_makefile_strict(content: &str) -> Result<MakefileConfig, ParseError> {
+    let (cfg, assigned) = parse(content);
+    let missing: Vec<String> = REQUIRED_VARIABLES
+        .iter()
+        .filter(|v| !assigned.contains(**v))
+        .map(|v| v.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(ParseError {
+            missing,
+            warnings: cfg.warnings,
+        });
+    }
+    Ok(cfg)
+}
+
+/// 解析 Makefile，同时返回出现过的变量
+fn parse(content: &str) -> (MakefileConfig, HashSet<String>) {
+    let re_assign = Regex::new(r"^([A-Za-z_][A-Za-z0-9_-]*)\s*([:+?]?=)\s*(.*)$").unwrap();
+    let re_ref = Regex::new(r"\$[({]([A-Za-z0-9_-]+)[)}]").unwrap();
+
     let lines: Vec<String> = content.lines().map(|l| l.to_string()).collect();
-    let lines = unfold_multiline(&lines);
+    let lines = unfold_multiline(&lines, &re_assign);
 
     let mut cfg = MakefileConfig {
         target: None,
         build_dir: None,
         c_sources: vec![],
         asm_sources: vec![],
+        startup_file: None,
+        cpp_sources: vec![],
         includes: vec![],
         defines: vec![],
+        define_pairs: vec![],
+        as_includes: vec![],
+        as_defines: vec![],
         cflags: vec![],
+        cxxflags: vec![],
         asflags: vec![],
         ldflags: vec![],
         libs: vec![],
         ldscript: None,
+        map_file: None,
+        opt: None,
+        cpu: None,
+        fpu: None,
+        float_abi: None,
+        debug: None,
+        debug_symbols: false,
+        prefix: None,
+        gcc_path: None,
+        extra: HashMap::new(),
+        warnings: vec![],
     };
+    let mut assigned = HashSet::new();
+    let mut warnings = Vec::new();
 
-    let mut include_set = HashSet::new();
-    let mut define_set = HashSet::new();
-
-    let re_assign = Regex::new(r"^([A-Z0-9_-]+)\s*[:+]?=\s*(.*)$").unwrap();
-
-    for line in lines {
+    // 先按赋值运算符收集每个变量的最终值：`=`/`:=` 覆盖，`+=` 追加，`?=` 仅在未定义时赋值
+    let mut vars: HashMap<String, String> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+    for raw in lines.iter() {
+        // 命令行交给 shell 处理，不去除注释
+        if raw.starts_with('\t') {
+            continue;
+        }
+        let line = strip_inline_comment(raw);
         let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
+        if line.is_empty() {
             continue;
         }
-
-        if let Some(cap) = re_assign.captures(line) {
-            let key = &cap[1];
-            let val = &cap[2];
-
-            match key {
-                "TARGET" => cfg.target = Some(val.into()),
-                "BUILD_DIR" => cfg.build_dir = Some(val.into()),
-                "C_SOURCES" => cfg
-                    .c_sources
-                    .extend(val.split_whitespace().map(|s| s.to_string())),
-                "ASM_SOURCES" => cfg
-                    .asm_sources
-                    .extend(val.split_whitespace().map(|s| s.to_string())),
-                "C_INCLUDES" | "AS_INCLUDES" => {
-                    for token in val.split_whitespace() {
-                        if token.starts_with("-I") {
-                            let path = token.trim_start_matches("-I").to_string();
-                            if include_set.insert(path.clone()) {
-                                cfg.includes.push(path);
-                            }
-                        }
-                    }
+        let Some(cap) = re_assign.captures(line) else {
+            if !is_rule_or_directive(line) {
+                warnings.push(format!("malformed line `{line}`"));
+            }
+            continue;
+        };
+        let key = cap[1].to_string();
+        let val = cap[3].trim().to_string();
+        if !vars.contains_key(&key) {
+            order.push(key.clone());
+        }
+        match &cap[2] {
+            "+=" => {
+                let entry = vars.entry(key).or_default();
+                if !entry.is_empty() {
+                    entry.push(' ');
                 }
-                "C_DEFS" | "AS_DEFS" => {
-                    for token in val.split_whitespace() {
-                        let name = if token.starts_with("-D") {
-                            token.trim_start_matches("-D").to_string()
-                        } else if token.starts_with("-include") {
-                            token.trim_start_matches("-include").trim().to_string()
-                        } else {
-                            token.to_string()
-                        };
-                        if !name.is_empty() && define_set.insert(name.clone()) {
-                            cfg.defines.push(name);
-                        }
-                    }
+                entry.push_str(&val);
+            }
+            "?=" => {
+                vars.entry(key).or_insert(val);
+            }
+            // `:=` 立即展开，允许 `X := $(X) ...` 这样的自引用
+            ":=" => {
+                let val = expand_variables(&val, &vars, &re_ref, &mut Vec::new());
+                vars.insert(key, val);
+            }
+            _ => {
+                vars.insert(key, val);
+            }
+        }
+    }
+
+    for key in order {
+        let val = expand_variables(&vars[&key], &vars, &re_ref, &mut warnings);
+        let val = val.as_str();
+        let key = key.as_str();
+        assigned.insert(key.to_string());
+
+        match key {
+            "TARGET" => cfg.target = Some(val.into()),
+            "BUILD_DIR" => cfg.build_dir = Some(val.into()),
+            "C_SOURCES" => cfg
+                .c_sources
+                .extend(val.split_whitespace().map(|s| s.to_string())),
+            "ASM_SOURCES" => cfg
+                .asm_sources
+                .extend(val.split_whitespace().map(|s| s.to_string())),
+            "CPP_SOURCES" => cfg
+                .cpp_sources
+                .extend(val.split_whitespace().map(|s| s.to_string())),
+            "C_INCLUDES" => cfg.includes = parse_includes(val),
+            "AS_INCLUDES" => cfg.as_includes = parse_includes(val),
+            "C_DEFS" => cfg.set_defines(parse_defines(val)),
+            "AS_DEFS" => cfg.as_defines = parse_defines(val),
+            "CFLAGS" => {
+                extract_arch_flags(&mut cfg, val);
+                if val.split_whitespace().any(is_debug_flag) {
+                    cfg.debug_symbols = true;
                 }
-                "CFLAGS" => cfg
-                    .cflags
-                    .extend(val.split_whitespace().map(|s| s.to_string())),
-                "ASFLAGS" => cfg
-                    .asflags
-                    .extend(val.split_whitespace().map(|s| s.to_string())),
-                "LDFLAGS" => cfg
-                    .ldflags
-                    .extend(val.split_whitespace().map(|s| s.to_string())),
-                "LIBS" => cfg
-                    .libs
-                    .extend(val.split_whitespace().map(|s| s.to_string())),
-                "LDSCRIPT" => cfg.ldscript = Some(val.into()),
-                _ => {}
+                cfg.cflags
+                    .extend(val.split_whitespace().map(|s| s.to_string()))
+            }
+            "CPU" | "FPU" | "FLOAT-ABI" | "MCU" => extract_arch_flags(&mut cfg, val),
+            "CXXFLAGS" => cfg
+                .cxxflags
+                .extend(val.split_whitespace().map(|s| s.to_string())),
+            "ASFLAGS" => cfg
+                .asflags
+                .extend(val.split_whitespace().map(|s| s.to_string())),
+            "LDFLAGS" => cfg
+                .ldflags
+                .extend(val.split_whitespace().map(|s| s.to_string())),
+            "LIBS" => cfg
+                .libs
+                .extend(val.split_whitespace().map(|s| s.to_string())),
+            "LDSCRIPT" => cfg.ldscript = Some(val.into()),
+            "OPT" => cfg.opt = Some(val.into()),
+            "DEBUG" => cfg.debug = parse_debug(val),
+            "PREFIX" => cfg.prefix = Some(val.into()),
+            "GCC_PATH" => cfg.gcc_path = Some(val.into()),
+            _ => {
+                warnings.push(format!("unrecognized variable `{key}`"));
+                cfg.extra.insert(key.to_string(), val.to_string());
             }
         }
     }
 
-    cfg
+    cfg.startup_file = cfg
+        .asm_sources
+        .iter()
+        .find(|source| {
+            Path::new(source)
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("startup_"))
+        })
+        .cloned();
+
+    // 如 -Wl,-Map=build/foo.map,--cref，逗号分隔的链接器参数中可能还有其他选项
+    cfg.map_file = cfg.ldflags.iter().find_map(|flag| {
+        flag.strip_prefix("-Wl,")?
+            .split(',')
+            .find_map(|arg| arg.strip_prefix("-Map="))
+            .map(|path| path.to_string())
+    });
+
+    cfg.warnings = warnings;
+    (cfg, assigned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiline_c_sources_with_comment_lines() {
+        let cfg = parse_makefile(
+            "\
+TARGET = demo
+C_SOURCES =  \\
+Core/Src/main.c \\
+Core/Src/gpio.c \\
+# HAL 驱动
+Drivers/STM32F4xx_HAL_Driver/Src/stm32f4xx_hal.c \\
+Drivers/STM32F4xx_HAL_Driver/Src/stm32f4xx_hal_rcc.c \\
+  # 系统文件
+Core/Src/system_stm32f4xx.c \\
+Core/Src/syscalls.c # newlib 桩函数 \\
+
+# ASM sources
+ASM_SOURCES =  \\
+startup_stm32f407xx.s
+",
+        );
+        assert_eq!(cfg.target.as_deref(), Some("demo"));
+        assert_eq!(
+            cfg.c_sources,
+            [
+                "Core/Src/main.c",
+                "Core/Src/gpio.c",
+                "Drivers/STM32F4xx_HAL_Driver/Src/stm32f4xx_hal.c",
+                "Drivers/STM32F4xx_HAL_Driver/Src/stm32f4xx_hal_rcc.c",
+                "Core/Src/system_stm32f4xx.c",
+                "Core/Src/syscalls.c",
+            ]
+        );
+        assert_eq!(cfg.asm_sources, ["startup_stm32f407xx.s"]);
+    }
+
+    #[test]
+    fn escaped_hash_in_continued_line() {
+        let cfg = parse_makefile("C_DEFS = \\\n-DCHANNEL=\\#1 \\\n-DUSE_HAL_DRIVER\n");
+        assert_eq!(cfg.defines, ["CHANNEL=#1", "USE_HAL_DRIVER"]);
+    }
+
+    #[test]
+    fn repeated_assignment_overrides() {
+        let cfg = parse_makefile("TARGET = first\nTARGET := second\nCFLAGS = -x\nCFLAGS = -y\n");
+        assert_eq!(cfg.target.as_deref(), Some("second"));
+        assert_eq!(cfg.cflags, ["-y"]);
+    }
+
+    #[test]
+    fn plus_equal_appends() {
+        let cfg = parse_makefile(
+            "C_SOURCES = main.c\nC_SOURCES += gpio.c\nC_SOURCES += usart.c\nLIBS += -lm\n",
+        );
+        assert_eq!(cfg.c_sources, ["main.c", "gpio.c", "usart.c"]);
+        assert_eq!(cfg.libs, ["-lm"]);
+    }
+
+    #[test]
+    fn question_equal_sets_only_if_undefined() {
+        let cfg = parse_makefile("BUILD_DIR = out\nBUILD_DIR ?= build\nOPT ?= -Og\nOPT ?= -O2\n");
+        assert_eq!(cfg.build_dir.as_deref(), Some("out"));
+        assert_eq!(cfg.opt.as_deref(), Some("-Og"));
+    }
+
+    #[test]
+    fn colon_equal_self_reference() {
+        let cfg =
+            parse_makefile("CFLAGS = -Wall\nCFLAGS := $(CFLAGS) -Og\nCFLAGS := $(CFLAGS) -g\n");
+        assert_eq!(cfg.cflags, ["-Wall", "-Og", "-g"]);
+        assert!(cfg.debug_symbols);
+    }
+
+    #[test]
+    fn inline_comments_on_sources_and_defines() {
+        let cfg = parse_makefile(
+            "\
+C_SOURCES = main.c gpio.c # entry point
+C_DEFS = -DUSE_HAL_DRIVER -DTAG=\"#tag\" -DCHANNEL=\\#1 # HAL 配置
+",
+        );
+        assert_eq!(cfg.c_sources, ["main.c", "gpio.c"]);
+        assert_eq!(
+            cfg.defines,
+            ["USE_HAL_DRIVER", "TAG=\"#tag\"", "CHANNEL=#1"]
+        );
+    }
+
+    #[test]
+    fn quoted_define_value_stays_intact() {
+        assert_eq!(
+            split_args(r#"-DUSE_HAL_DRIVER -DVERSION="1.0 beta" -DNAME='\"foo bar\"'"#),
+            [
+                "-DUSE_HAL_DRIVER",
+                r#"-DVERSION="1.0 beta""#,
+                r#"-DNAME='\"foo bar\"'"#
+            ]
+        );
+        let cfg = parse_makefile("C_DEFS = -DUSE_HAL_DRIVER -DVERSION=\"1.0 beta\"\n");
+        assert_eq!(cfg.defines, ["USE_HAL_DRIVER", "VERSION=\"1.0 beta\""]);
+        assert_eq!(
+            cfg.define_pairs[1],
+            ("VERSION".to_string(), Some("\"1.0 beta\"".to_string()))
+        );
+    }
+
+    #[test]
+    fn equivalent_include_paths_dedupe() {
+        let cfg = parse_makefile(
+            "C_INCLUDES = -I./Core/Inc -ICore/Inc -IDrivers/CMSIS/../CMSIS/Include -IDrivers/CMSIS/Include\n",
+        );
+        assert_eq!(cfg.includes, ["Core/Inc", "Drivers/CMSIS/Include"]);
+    }
+
+    #[test]
+    fn normalize_parent_dirs() {
+        assert_eq!(normalize_path("Core/Inc/.."), "Core");
+        assert_eq!(normalize_path("Core/.."), ".");
+        assert_eq!(normalize_path("../lib/./Inc"), "../lib/Inc");
+        assert_eq!(normalize_path("/opt/../usr/include"), "/usr/include");
+    }
+
+    #[test]
+    fn lenient_parse_collects_warnings() {
+        let cfg = parse_makefile("TARGET = demo\nFOO = $(BAR)\nnot an assignment\n");
+        assert_eq!(
+            cfg.warnings,
+            [
+                "malformed line `not an assignment`",
+                "unresolved reference `$(BAR)`",
+                "unrecognized variable `FOO`",
+            ]
+        );
+    }
+
+    #[test]
+    fn include_flags_are_not_defines() {
+        let cfg = parse_makefile(
+            "C_DEFS = -DUSE_HAL_DRIVER -include foo.h -D STM32F407xx -includebar.h -DDEBUG=1\n",
+        );
+        assert_eq!(cfg.defines, ["USE_HAL_DRIVER", "STM32F407xx", "DEBUG=1"]);
+        assert_eq!(
+            cfg.define_flags(),
+            ["-DUSE_HAL_DRIVER", "-DSTM32F407xx", "-DDEBUG=1"]
+        );
+    }
+
+    #[test]
+    fn opt_arch_and_debug_extraction() {
+        let cases = [
+            ("OPT = -Og", Some("-Og"), None, None, None, None),
+            (
+                "CPU = -mcpu=cortex-m4\nFPU = -mfpu=fpv4-sp-d16\nFLOAT-ABI = -mfloat-abi=hard",
+                None,
+                Some("cortex-m4"),
+                Some("fpv4-sp-d16"),
+                Some("hard"),
+                None,
+            ),
+            (
+                "CFLAGS = -mcpu=cortex-m7 -mthumb -mfpu=fpv5-d16 -mfloat-abi=softfp -O2",
+                None,
+                Some("cortex-m7"),
+                Some("fpv5-d16"),
+                Some("softfp"),
+                None,
+            ),
+            (
+                "MCU = -mcpu=cortex-m0 -mthumb",
+                None,
+                Some("cortex-m0"),
+                None,
+                None,
+                None,
+            ),
+            (
+                "DEBUG = 1\nOPT = -O2",
+                Some("-O2"),
+                None,
+                None,
+                None,
+                Some(true),
+            ),
+            ("DEBUG = 0", None, None, None, None, Some(false)),
+            ("DEBUG = yes", None, None, None, None, None),
+        ];
+        for (content, opt, cpu, fpu, float_abi, debug) in cases {
+            let cfg = parse_makefile(content);
+            assert_eq!(cfg.opt.as_deref(), opt, "{content}");
+            assert_eq!(cfg.cpu.as_deref(), cpu, "{content}");
+            assert_eq!(cfg.fpu.as_deref(), fpu, "{content}");
+            assert_eq!(cfg.float_abi.as_deref(), float_abi, "{content}");
+            assert_eq!(cfg.debug, debug, "{content}");
+        }
+    }
+
+    #[test]
+    fn debug_symbols_from_cflags() {
+        let cases = [
+            ("CFLAGS = -Wall -g", true),
+            ("CFLAGS = -g3 -O0", true),
+            ("CFLAGS = -gdwarf-2", true),
+            ("CFLAGS = -Wall -O2 -gc-sections", false),
+            ("CFLAGS = -Wall", false),
+        ];
+        for (content, debug_symbols) in cases {
+            assert_eq!(
+                parse_makefile(content).debug_symbols,
+                debug_symbols,
+                "{content}"
+            );
+        }
+    }
+
+    fn expand(val: &str, vars: &[(&str, &str)]) -> (String, Vec<String>) {
+        let re_ref = Regex::new(r"\$[({]([A-Za-z0-9_-]+)[)}]").unwrap();
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut warnings = Vec::new();
+        let result = expand_variables(val, &vars, &re_ref, &mut warnings);
+        (result, warnings)
+    }
+
+    #[test]
+    fn expand_variable_references() {
+        let vars = [
+            ("TARGET", "demo"),
+            ("BUILD_DIR", "build"),
+            ("OUT", "$(BUILD_DIR)/${TARGET}"),
+        ];
+        let cases = [
+            ("$(TARGET).elf", "demo.elf"),
+            ("${TARGET}.hex", "demo.hex"),
+            ("$(OUT).bin", "build/demo.bin"),
+            ("plain", "plain"),
+        ];
+        for (val, expected) in cases {
+            assert_eq!(expand(val, &vars), (expected.to_string(), vec![]), "{val}");
+        }
+    }
+
+    #[test]
+    fn expand_stops_at_max_depth_on_cycles() {
+        let (result, warnings) = expand("$(A)", &[("A", "x$(B)"), ("B", "y$(A)")]);
+        // 每层展开一个引用，达到最大层数后保留未展开的引用
+        assert_eq!(result, format!("{}$(A)", "xy".repeat(MAX_EXPAND_DEPTH / 2)));
+        assert_eq!(warnings, ["unresolved reference `$(A)`"]);
+    }
+
+    #[test]
+    fn unresolved_references_are_kept_and_reported() {
+        let (result, warnings) = expand(
+            "-I$(MISSING) -I${ALSO_MISSING} $(TARGET)",
+            &[("TARGET", "demo")],
+        );
+        assert_eq!(result, "-I$(MISSING) -I${ALSO_MISSING} demo");
+        assert_eq!(
+            warnings,
+            [
+                "unresolved reference `$(MISSING)`",
+                "unresolved reference `${ALSO_MISSING}`"
+            ]
+        );
+
+        let cfg = parse_makefile("TARGET = $(NAME)\n");
+        assert_eq!(cfg.target.as_deref(), Some("$(NAME)"));
+        assert_eq!(cfg.warnings, ["unresolved reference `$(NAME)`"]);
+    }
+
+    #[test]
+    fn mixed_case_variables_go_to_extra() {
+        let cfg = parse_makefile(
+            "TARGET = demo\nTarget = other\nc_sources = foo.c\nC_SOURCES = main.c\n",
+        );
+        assert_eq!(cfg.target.as_deref(), Some("demo"));
+        assert_eq!(cfg.c_sources, ["main.c"]);
+        assert_eq!(cfg.extra.len(), 2);
+        assert_eq!(cfg.extra["Target"], "other");
+        assert_eq!(cfg.extra["c_sources"], "foo.c");
+    }
+
+    #[test]
+    fn strict_parse_reports_missing_variables() {
+        let cases: [(&str, &[&str]); 4] = [
+            ("TARGET = demo\nC_SOURCES = main.c\n", &[]),
+            ("C_SOURCES = main.c\n", &["TARGET"]),
+            ("TARGET = demo\n", &["C_SOURCES"]),
+            ("OPT = -Og\n", &["TARGET", "C_SOURCES"]),
+        ];
+        for (content, missing) in cases {
+            match parse_makefile_strict(content) {
+                Ok(cfg) => assert!(missing.is_empty(), "{content}: {cfg:?}"),
+                Err(e) => assert_eq!(e.missing, missing, "{content}"),
+            }
+        }
+
+        let err = parse_makefile_strict("OPT = -Og\nFOO = 1\n").unwrap_err();
+        assert_eq!(err.warnings, ["unrecognized variable `FOO`"]);
+        assert_eq!(
+            err.to_string(),
+            "missing required variables: TARGET, C_SOURCES (1 warnings)"
+        );
+    }
+
+    #[test]
+    fn cpp_sources_and_cxxflags() {
+        let cfg = parse_makefile(
+            "\
+C_SOURCES = Core/Src/main.c
+CPP_SOURCES = \\
+UserCode/app/app.cpp \\
+UserCode/bsp/led.cpp
+CPP_SOURCES += UserCode/libs/pid.cpp
+CXXFLAGS = $(CFLAGS) -fno-rtti -fno-exceptions
+CFLAGS = -mcpu=cortex-m4 -Wall
+",
+        );
+        assert_eq!(cfg.c_sources, ["Core/Src/main.c"]);
+        assert_eq!(
+            cfg.cpp_sources,
+            [
+                "UserCode/app/app.cpp",
+                "UserCode/bsp/led.cpp",
+                "UserCode/libs/pid.cpp"
+            ]
+        );
+        assert_eq!(
+            cfg.cxxflags,
+            ["-mcpu=cortex-m4", "-Wall", "-fno-rtti", "-fno-exceptions"]
+        );
+    }
+
+    #[test]
+    fn startup_and_map_file_detection() {
+        let cases = [
+            (
+                "ASM_SOURCES = Core/Src/irq.s startup_stm32f407xx.s\nLDFLAGS = -Wl,-Map=$(BUILD_DIR)/$(TARGET).map,--cref -Wl,--gc-sections\nBUILD_DIR = build\nTARGET = demo",
+                Some("startup_stm32f407xx.s"),
+                Some("build/demo.map"),
+            ),
+            (
+                "ASM_SOURCES = Core/Startup/startup_stm32h743xx.s\nLDFLAGS = -specs=nano.specs -Wl,--gc-sections,-Map=out.map",
+                Some("Core/Startup/startup_stm32h743xx.s"),
+                Some("out.map"),
+            ),
+            (
+                "ASM_SOURCES = Core/Src/irq.s\nLDFLAGS = -Wl,--gc-sections",
+                None,
+                None,
+            ),
+        ];
+        for (content, startup_file, map_file) in cases {
+            let cfg = parse_makefile(content);
+            assert_eq!(cfg.startup_file.as_deref(), startup_file, "{content}");
+            assert_eq!(cfg.map_file.as_deref(), map_file, "{content}");
+        }
+    }
 }
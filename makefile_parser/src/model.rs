@@ -1,4 +1,9 @@
 use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// STM32CubeMX 生成的 Makefile 默认的工具链前缀
+const DEFAULT_PREFIX: &str = "arm-none-eabi-";
 
 #[derive(Debug, Serialize)]
 pub struct MakefileConfig {
@@ -6,11 +11,82 @@ pub struct MakefileConfig {
     pub build_dir: Option<String>,
     pub c_sources: Vec<String>,
     pub asm_sources: Vec<String>,
+    pub startup_file: Option<String>, // ASM_SOURCES 中以 startup_ 开头的启动文件
+    pub cpp_sources: Vec<String>,
     pub includes: Vec<String>,
-    pub defines: Vec<String>, // 简化为字符串
+    pub defines: Vec<String>,                        // 简化为字符串
+    pub define_pairs: Vec<(String, Option<String>)>, // (宏名, 宏值)，用于还原 -D 参数
+    pub as_includes: Vec<String>,                    // AS_INCLUDES，汇编使用的头文件路径
+    pub as_defines: Vec<String>,                     // AS_DEFS，汇编使用的宏定义
     pub cflags: Vec<String>,
+    pub cxxflags: Vec<String>,
     pub asflags: Vec<String>,
     pub ldflags: Vec<String>,
     pub libs: Vec<String>,
     pub ldscript: Option<String>,
+    pub map_file: Option<String>, // LDFLAGS 中 -Wl,-Map= 指定的 map 文件
+    pub opt: Option<String>,      // 优化等级，如 -Og
+    pub cpu: Option<String>,      // -mcpu，如 cortex-m4
+    pub fpu: Option<String>,      // -mfpu，如 fpv4-sp-d16
+    pub float_abi: Option<String>, // -mfloat-abi，如 hard
+    pub debug: Option<bool>,      // DEBUG = 1/0
+    pub debug_symbols: bool,      // CFLAGS 中是否含 -g/-gdwarf-2 等调试信息参数
+    pub prefix: Option<String>,   // 工具链前缀，如 arm-none-eabi-
+    pub gcc_path: Option<String>, // 工具链 bin 目录
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub extra: HashMap<String, String>, // 无法识别的变量，值已展开
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>, // 无法识别的变量、格式错误的行与无法解析的引用
+}
+
+impl MakefileConfig {
+    /// 设置宏定义，同时更新 `define_pairs`
+    pub fn set_defines(&mut self, defines: Vec<String>) {
+        self.define_pairs = defines
+            .iter()
+            .map(|name| match name.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (name.clone(), None),
+            })
+            .collect();
+        self.defines = defines;
+    }
+
+    /// 还原为 `-D` 编译参数
+    pub fn define_flags(&self) -> Vec<String> {
+        self.define_pairs
+            .iter()
+            .map(|(name, value)| match value {
+                Some(value) => format!("-D{name}={value}"),
+                None => format!("-D{name}"),
+            })
+            .collect()
+    }
+
+    /// 工具链前缀，未设置 PREFIX 时为 arm-none-eabi-
+    pub fn tool_prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(DEFAULT_PREFIX)
+    }
+
+    /// C 编译器，如 arm-none-eabi-gcc，设置了 GCC_PATH 时带上目录
+    pub fn c_compiler(&self) -> String {
+        self.tool("gcc")
+    }
+
+    /// 工具链中的工具，如 `tool("size")` 为 arm-none-eabi-size，设置了 GCC_PATH 时带上目录
+    pub fn tool(&self, name: &str) -> String {
+        let tool = format!("{}{}", self.tool_prefix(), name);
+        match &self.gcc_path {
+            Some(gcc_path) => format!("{}/{}", gcc_path.trim_end_matches('/'), tool),
+            None => tool,
+        }
+    }
+
+    /// 将 LDSCRIPT 解析为相对于 Makefile 所在目录 `base` 的路径
+    ///
+    /// 未设置或文件不存在时返回 None
+    pub fn resolve_ldscript(&self, base: &Path) -> Option<PathBuf> {
+        let path = base.join(self.ldscript.as_deref()?);
+        path.is_file().then_some(path)
+    }
 }
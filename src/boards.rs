@@ -0,0 +1,57 @@
+/// 开发板配置，创建项目时用于选择芯片和外部晶振频率
+#[derive(Debug)]
+pub struct Board {
+    /// 开发板名，匹配时不区分大小写
+    pub name: &'static str,
+    /// STM32CubeMX 中的芯片型号，如 STM32F407VETx
+    pub mcu: &'static str,
+    /// 外部高速晶振频率 (Hz)
+    pub hse_value: u32,
+}
+
+/// 内置的开发板
+pub const BOARDS: [Board; 7] = [
+    Board {
+        name: "f407ve",
+        mcu: "STM32F407VETx",
+        hse_value: 8_000_000,
+    },
+    Board {
+        name: "robomaster-c",
+        mcu: "STM32F407IGHx",
+        hse_value: 12_000_000,
+    },
+    Board {
+        name: "nucleo-f401re",
+        mcu: "STM32F401RETx",
+        hse_value: 8_000_000,
+    },
+    Board {
+        name: "nucleo-f446re",
+        mcu: "STM32F446RETx",
+        hse_value: 8_000_000,
+    },
+    Board {
+        name: "nucleo-f103rb",
+        mcu: "STM32F103RBTx",
+        hse_value: 8_000_000,
+    },
+    Board {
+        name: "bluepill",
+        mcu: "STM32F103C8Tx",
+        hse_value: 8_000_000,
+    },
+    Board {
+        name: "blackpill-f411",
+        mcu: "STM32F411CEUx",
+        hse_value: 25_000_000,
+    },
+];
+
+/// 未指定开发板时使用的芯片
+pub const DEFAULT_MCU: &str = "STM32F407VETx";
+
+/// 按名称查找开发板
+pub fn find_board(name: &str) -> Option<&'static Board> {
+    BOARDS.iter().find(|b| b.name.eq_ignore_ascii_case(name))
+}
@@ -0,0 +1,121 @@
+use makefile_parser::split_args;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+
+/// compile_commands.json 的常见位置，按顺序查找，相对于项目根目录
+const COMPILE_COMMANDS_FILES: [&str; 3] = [
+    "compile_commands.json",
+    "build/compile_commands.json",
+    "build/Debug/compile_commands.json",
+];
+
+/// compile_commands.json 中的一条编译命令，`arguments` 与 `command` 二选一
+#[derive(Debug, Deserialize)]
+struct CompileCommand {
+    directory: String,
+    #[serde(default)]
+    arguments: Option<Vec<String>>,
+    #[serde(default)]
+    command: Option<String>,
+}
+
+/// 从编译命令中提取的头文件路径与宏定义
+#[derive(Debug, Default)]
+pub struct CompileFlags {
+    /// 相对于项目根目录的路径，不在项目内时为绝对路径
+    pub includes: Vec<String>,
+    /// 宏定义，如 USE_HAL_DRIVER、HSE_VALUE=8000000
+    pub defines: Vec<String>,
+}
+
+/// 查找项目中的 compile_commands.json
+pub fn find_compile_commands(root: &Path) -> Option<PathBuf> {
+    COMPILE_COMMANDS_FILES
+        .iter()
+        .map(|file| root.join(file))
+        .find(|path| path.is_file())
+}
+
+/// 读取 compile_commands.json，合并所有编译命令中的 `-I`、`-D` 参数并去重
+///
+/// 文件无法解析时输出警告并返回 None
+pub fn load_compile_flags(root: &Path, path: &Path) -> Option<CompileFlags> {
+    let commands = fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| {
+            serde_json::from_str::<Vec<CompileCommand>>(&content).map_err(|e| e.to_string())
+        });
+    let commands = match commands {
+        Ok(commands) => commands,
+        Err(e) => {
+            warn!("Invalid {}, ignoring it: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    let root = if root.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        root
+    };
+    let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+    let mut flags = CompileFlags::default();
+    for command in commands {
+        let args = match (command.arguments, command.command) {
+            (Some(arguments), _) => arguments,
+            (None, Some(command)) => split_args(&command),
+            (None, None) => continue,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // 同时支持 `-Ipath` 与 `-I path` 两种写法
+            let (include, define) = match arg.as_str() {
+                "-I" => (args.next(), None),
+                "-D" => (None, args.next()),
+                _ => (
+                    arg.strip_prefix("-I").map(str::to_string),
+                    arg.strip_prefix("-D").map(str::to_string),
+                ),
+            };
+            if let Some(include) = include {
+                let include = relative_to_root(&root, &Path::new(&command.directory).join(include));
+                if !flags.includes.contains(&include) {
+                    flags.includes.push(include);
+                }
+            }
+            if let Some(define) = define
+                && !flags.defines.contains(&define)
+            {
+                flags.defines.push(define);
+            }
+        }
+    }
+    Some(flags)
+}
+
+/// 将编译命令中的路径转换为相对于项目根目录的路径，不在项目内时保留绝对路径
+fn relative_to_root(root: &Path, path: &Path) -> String {
+    let path = path.canonicalize().unwrap_or_else(|_| normalize(path));
+    let path = match path.strip_prefix(root) {
+        Ok(relative) if relative.as_os_str().is_empty() => Path::new("."),
+        Ok(relative) => relative,
+        Err(_) => &path,
+    };
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// 不访问文件系统，折叠路径中的 `.` 与 `dir/..`，用于不存在的路径
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if !normalized.pop() => normalized.push(".."),
+            Component::ParentDir => {}
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,214 @@
+use crate::patches::Patch;
+use glob::{glob_with, MatchOptions, Pattern};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// 项目根目录下的配置文件，按顺序查找
+const CONFIG_FILES: [&str; 2] = ["stm32-tool.toml", "stm32-tool.json"];
+
+const DEFAULT_APP_DIR: &str = "UserCode/app";
+const DEFAULT_DRIVERS_DIR: &str = "UserCode/drivers";
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// 用户代码目录结构
+    pub directories: Vec<String>,
+    /// 存放 app.h/app.c 的目录，缺省时取 directories 中名为 app 的目录
+    pub app_dir: Option<String>,
+    /// EIDE 源码目录的匹配规则
+    pub source_dirs: SourceDirs,
+    /// 项目自定义的补丁，在内置补丁之后应用，路径相对于项目根目录
+    pub patches: Vec<Patch>,
+}
+
+/// 以 glob 匹配源码目录，相对于项目根目录，如 `Core`、`UserCode/*`
+///
+/// 默认为根目录下不以 `.` 开头的目录
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct SourceDirs {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Default for SourceDirs {
+    fn default() -> Self {
+        SourceDirs {
+            include: vec!["*".to_string()],
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            directories: [
+                "UserCode/bsp",
+                DEFAULT_DRIVERS_DIR,
+                "UserCode/third_party",
+                "UserCode/libs",
+                "UserCode/interfaces",
+                "UserCode/controllers",
+                DEFAULT_APP_DIR,
+            ]
+            .iter()
+            .map(|d| d.to_string())
+            .collect(),
+            app_dir: None,
+            source_dirs: SourceDirs::default(),
+            patches: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// 读取项目根目录下的配置，不存在或解析失败时使用默认配置
+    pub fn load(root: &Path) -> Config {
+        for file in CONFIG_FILES {
+            let Ok(content) = fs::read_to_string(root.join(file)) else {
+                continue;
+            };
+            let config = if file.ends_with(".toml") {
+                toml::from_str(&content).map_err(|e| e.to_string())
+            } else {
+                serde_json::from_str(&content).map_err(|e| e.to_string())
+            };
+            match config {
+                Ok(config) => {
+                    info!("Loaded config from {}", file);
+                    return config;
+                }
+                Err(e) => {
+                    warn!("Invalid {}, using default config: {}", file, e);
+                    return Config::default();
+                }
+            }
+        }
+        Config::default()
+    }
+
+    pub fn app_dir(&self) -> &str {
+        if let Some(dir) = &self.app_dir {
+            return dir.trim_end_matches('/');
+        }
+        self.directories
+            .iter()
+            .find(|d| Path::new(d).file_name().is_some_and(|n| n == "app"))
+            .map(|d| d.trim_end_matches('/'))
+            .unwrap_or(DEFAULT_APP_DIR)
+    }
+
+    /// 存放驱动桩的目录，取 directories 中名为 drivers 的目录
+    pub fn drivers_dir(&self) -> &str {
+        self.directories
+            .iter()
+            .find(|d| Path::new(d).file_name().is_some_and(|n| n == "drivers"))
+            .map(|d| d.trim_end_matches('/'))
+            .unwrap_or(DEFAULT_DRIVERS_DIR)
+    }
+
+    /// 按 source_dirs 查找源码目录，忽略无效的模式，结果相对于项目根目录，按匹配顺序去重
+    pub fn find_source_dirs(&self, root: &Path) -> Vec<String> {
+        // `*` 不匹配以 `.` 开头的目录，如 .git、.eide
+        let options = MatchOptions {
+            require_literal_leading_dot: true,
+            ..Default::default()
+        };
+        let exclude: Vec<Pattern> = self
+            .source_dirs
+            .exclude
+            .iter()
+            .filter_map(|p| match Pattern::new(p.trim_end_matches('/')) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    warn!("Invalid exclude pattern `{}`: {}", p, e);
+                    None
+                }
+            })
+            .collect();
+
+        let root_pattern = Pattern::escape(&root.to_string_lossy());
+        let mut dirs = Vec::new();
+        for pattern in &self.source_dirs.include {
+            let full_pattern = Path::new(&root_pattern).join(pattern.trim_end_matches('/'));
+            let paths = match glob_with(&full_pattern.to_string_lossy(), options) {
+                Ok(paths) => paths,
+                Err(e) => {
+                    warn!("Invalid include pattern `{}`: {}", pattern, e);
+                    continue;
+                }
+            };
+            for path in paths.flatten() {
+                if !path.is_dir() {
+                    continue;
+                }
+                let path = path.strip_prefix(root).unwrap_or(&path);
+                if exclude.iter().any(|p| p.matches_path_with(path, options)) {
+                    continue;
+                }
+                let dir = path.to_string_lossy().replace('\\', "/");
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+        }
+        dirs
+    }
+
+    /// 用户代码的顶层目录，取 directories 与 app 目录的第一级，如 UserCode，按出现顺序去重
+    pub fn user_code_dirs(&self) -> Vec<&str> {
+        let mut dirs: Vec<&str> = Vec::new();
+        for dir in self
+            .directories
+            .iter()
+            .map(String::as_str)
+            .chain([self.app_dir()])
+        {
+            let top = dir.split('/').next().unwrap_or(dir);
+            if !top.is_empty() && !dirs.contains(&top) {
+                dirs.push(top);
+            }
+        }
+        dirs
+    }
+
+    /// README 放在 app 目录的上一级，如 UserCode/README.md
+    pub fn readme_path(&self) -> String {
+        match Path::new(self.app_dir()).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                format!("{}/README.md", parent.to_string_lossy())
+            }
+            _ => format!("{}/README.md", self.app_dir()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_dirs_exclude_build_and_git() {
+        let root = tempfile::tempdir().unwrap();
+        for dir in ["Core", "Drivers", "UserCode/app", "build/Debug", ".git"] {
+            fs::create_dir_all(root.path().join(dir)).unwrap();
+        }
+        fs::write(root.path().join("Makefile"), "").unwrap();
+
+        let mut config = Config::default();
+        assert_eq!(
+            config.find_source_dirs(root.path()),
+            ["Core", "Drivers", "UserCode", "build"]
+        );
+
+        config.source_dirs.exclude = vec!["build/".to_string()];
+        assert_eq!(
+            config.find_source_dirs(root.path()),
+            ["Core", "Drivers", "UserCode"]
+        );
+    }
+}
@@ -3,18 +3,102 @@ use serde::Serialize;
 #[derive(Serialize)]
 pub struct EIDEConfigContext<'a> {
     pub project_name: &'a String,
-    pub ld_file_path: &'a String,
     pub src_dirs: &'a String,
-    pub include_list: &'a String,
-    pub define_list: &'a String,
     pub src_files: &'a String,
+    pub targets: Vec<EIDETargetContext<'a>>,
+    pub gcc_prefix: String,                   // 已转义为 JSON 字符串
+    pub gcc_install_dir: Option<String>,      // 已转义为 JSON 字符串
+    pub uploader: &'static str,               // EIDE 的烧录器，如 STLink、OpenOCD
+    pub openocd_target: Option<&'static str>, // 使用 OpenOCD 烧录时的 target 脚本名
+    pub openocd_interface: &'static str,
+    pub chip_name: String,   // 如 STM32F407VE
+    pub device_name: String, // 已转义为 JSON 字符串，无法识别芯片时为 null
+}
+
+/// EIDE 的构建配置，如 Debug、Release
+#[derive(Serialize)]
+pub struct EIDETargetContext<'a> {
+    pub name: &'static str,
+    pub id: &'static str, // 小写的配置名，用于文件名
+    pub optimization: &'static str,
+    pub debug_info: bool,
+    pub define_list: String, // 已转义为 JSON 数组
+    pub asm_flags: String,   // 已转义为 JSON 字符串
+    pub ld_file_path: &'a String,
+    pub include_list: &'a String,
+    pub cpu_type: &'a str,
+    pub floating_point_hardware: &'a str,
+    pub float_abi_type: &'a str,
 }
 
 #[derive(Serialize)]
 pub struct CreateContext<'a> {
     pub project_name: &'a String,
+    pub mcu: &'a str,
     pub project_dir: &'a String,
     pub ioc_file_path: &'a String,
     pub toolchain: &'a str,
     pub generate_under_root: bool,
+    pub couple_files: bool,
+}
+
+#[derive(Serialize)]
+pub struct LaunchContext<'a> {
+    pub executable: &'a String,
+    pub device: &'a String,
+    pub probe: &'a str,
+    pub servertype: &'a str,
+    pub openocd: bool, // 通过 OpenOCD 调试时需要指定 openocd.cfg
+}
+
+#[derive(Serialize)]
+pub struct TasksContext<'a> {
+    pub elf: &'a String,
+    pub bin: &'a String,
+}
+
+#[derive(Serialize)]
+pub struct DriverContext<'a> {
+    pub author: &'a String,
+    pub email: &'a String,
+    pub date: &'a String,
+    pub name: &'a String,
+    pub guard: &'a String,
+    pub instances: &'a String, // 同类外设的实例，如 USART1, USART2
+}
+
+#[derive(Serialize)]
+pub struct ReadmeContext<'a> {
+    pub directories: &'a String, // 已排版的目录树
+}
+
+#[derive(Serialize)]
+pub struct ClangFormatContext {
+    pub style: &'static str, // clang-format 的 BasedOnStyle，如 Google
+}
+
+#[derive(Serialize)]
+pub struct DoxygenContext<'a> {
+    pub project_name: &'a str,
+    pub input: &'a str,    // 以空格分隔的源码目录，如 UserCode Core
+    pub mainpage: &'a str, // 作为首页的 README
+}
+
+#[derive(Serialize)]
+pub struct OpenOCDContext<'a> {
+    pub interface: &'a str,
+    pub target: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct ClangdContext {
+    pub compiler: String,   // 已转义为 YAML 字符串
+    pub flags: Vec<String>, // 已转义为 YAML 字符串的编译参数
+}
+
+#[derive(Serialize)]
+pub struct CppPropertiesContext {
+    pub include_path: String,  // 已转义为 JSON 数组
+    pub defines: String,       // 已转义为 JSON 数组
+    pub compiler_path: String, // 已转义为 JSON 字符串
 }
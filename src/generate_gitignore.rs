@@ -1,10 +1,12 @@
 use chrono::Local;
 use include_dir::{include_dir, Dir};
 use serde::Deserialize;
-use std::fs::{self, File};
-use std::io::{self, Write};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::io;
 use std::path::Path;
-use tracing::{error, warn};
+use tracing::{error, info, warn};
 
 static DEFAULT_GITIGNORE_CONFIG_DIR: Dir = include_dir!("src/configs/gitignore");
 
@@ -14,7 +16,7 @@ struct GitignoreConfig {
     description: String,
     enabled: bool,
     ignore: Option<Vec<String>>,
-    sections: Option<std::collections::HashMap<String, SubSection>>,
+    sections: Option<std::collections::BTreeMap<String, SubSection>>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -52,15 +54,111 @@ fn iter_gitignore_configs(config_dir: Option<&str>) -> Box<dyn Iterator<Item=Git
     }
 }
 
-pub fn generate_gitignore(config_dir: Option<&str>, is_force: bool) -> io::Result<()> {
-    const PATH: &str = ".gitignore";
+/// 合并模式下 STM32 规则块的起止标记
+const MERGE_BEGIN: &str = "# === STM32 tool ===";
+const MERGE_END: &str = "# === end STM32 tool ===";
 
-    if Path::new(PATH).exists() && !is_force {
-        warn!("Skip existing {}", PATH);
-        return Ok(());
+/// 生成 .gitignore
+///
+/// `path` 为写入的路径，`build_dir` 为构建输出目录，不在默认规则中时会额外加入。
+/// 文件已存在时：`is_force` 覆盖，`merge` 仅追加缺少的规则到 STM32 规则块，否则跳过。
+/// 返回文件是否被写入
+pub fn generate_gitignore(
+    path: &str,
+    config_dir: Option<&str>,
+    build_dir: &str,
+    is_force: bool,
+    merge: bool,
+    dry_run: bool,
+) -> io::Result<bool> {
+    let exists = Path::new(path).exists();
+    if exists && !is_force && merge {
+        return merge_gitignore(path, config_dir, build_dir, dry_run);
     }
+    if exists && !is_force {
+        warn!("Skip existing {}", path);
+        return Ok(false);
+    }
+
+    if dry_run {
+        info!("[dry-run] Would write {}", path);
+        return Ok(true);
+    }
+
+    fs::write(
+        path,
+        render_gitignore(config_dir, build_dir).map_err(io::Error::other)?,
+    )?;
+    Ok(true)
+}
+
+/// 将已有 .gitignore 中没有的规则写入 STM32 规则块，已有的块会被重新生成
+fn merge_gitignore(
+    path: &str,
+    config_dir: Option<&str>,
+    build_dir: &str,
+    dry_run: bool,
+) -> io::Result<bool> {
+    let content = fs::read_to_string(path)?;
+
+    // 去掉上次合并的块，其余为用户自己的规则
+    let mut user_lines = Vec::new();
+    let mut in_block = false;
+    for line in content.lines() {
+        match line.trim() {
+            MERGE_BEGIN => in_block = true,
+            MERGE_END => in_block = false,
+            _ if !in_block => user_lines.push(line),
+            _ => {}
+        }
+    }
+    while user_lines.last().is_some_and(|l| l.trim().is_empty()) {
+        user_lines.pop();
+    }
+
+    let existing: HashSet<&str> = user_lines.iter().map(|l| l.trim()).collect();
+    let mut added = HashSet::new();
+    let missing: Vec<String> = render_gitignore(config_dir, build_dir)
+        .map_err(io::Error::other)?
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter(|l| !existing.contains(l) && added.insert(*l))
+        .map(|l| l.to_string())
+        .collect();
+
+    // 用户规则已包含全部规则时保留原文件，不移除已有的块
+    if missing.is_empty() {
+        info!("{} already contains all rules", path);
+        return Ok(false);
+    }
+
+    let mut new_content = user_lines.join("\n");
+    if !new_content.is_empty() {
+        new_content.push_str("\n\n");
+    }
+    new_content.push_str(MERGE_BEGIN);
+    new_content.push('\n');
+    new_content.push_str(&missing.join("\n"));
+    new_content.push('\n');
+    new_content.push_str(MERGE_END);
+    new_content.push('\n');
+    if new_content == content {
+        info!("{} already contains all rules", path);
+        return Ok(false);
+    }
+
+    if dry_run {
+        info!("[dry-run] Would merge {} rules into {}", missing.len(), path);
+        return Ok(true);
+    }
+    fs::write(path, new_content)?;
+    info!("Merged {} rules into {}", missing.len(), path);
+    Ok(true)
+}
 
-    let mut file = File::create(PATH)?;
+fn render_gitignore(config_dir: Option<&str>, build_dir: &str) -> Result<String, std::fmt::Error> {
+    let mut file = String::new();
 
     let now = Local::now();
     writeln!(file, "# generated on {}", now.format("%Y-%m-%d %H:%M:%S"))?;
@@ -102,5 +200,46 @@ pub fn generate_gitignore(config_dir: Option<&str>, is_force: bool) -> io::Resul
 
         writeln!(file)?; // 空行分隔
     }
-    Ok(())
+
+    let build_dir = format!("{}/", build_dir.trim_start_matches("./").trim_end_matches('/'));
+    if build_dir != "/" && !file.lines().any(|l| l.trim() == build_dir) {
+        writeln!(file, "### Makefile build directory ###")?;
+        writeln!(file, "{}", build_dir)?;
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_twice_keeps_user_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".gitignore");
+        let path = path.to_str().unwrap();
+        fs::write(path, "# 用户规则\n*.log\nsecrets/\n").unwrap();
+
+        assert!(generate_gitignore(path, None, "out", false, true, false).unwrap());
+        let merged = fs::read_to_string(path).unwrap();
+        assert!(merged.starts_with("# 用户规则\n*.log\nsecrets/\n\n# === STM32 tool ===\n"));
+        assert!(merged.lines().any(|l| l == "out/"));
+        assert!(merged.ends_with("# === end STM32 tool ===\n"));
+
+        assert!(!generate_gitignore(path, None, "out", false, true, false).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), merged);
+    }
+
+    #[test]
+    fn merge_keeps_block_when_nothing_is_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".gitignore");
+        let path = path.to_str().unwrap();
+        let rules = render_gitignore(None, "build").unwrap();
+        let content = format!("{rules}\n{MERGE_BEGIN}\nbuild/\n{MERGE_END}\n");
+        fs::write(path, &content).unwrap();
+
+        assert!(!generate_gitignore(path, None, "build", false, true, false).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), content);
+    }
 }
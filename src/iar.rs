@@ -0,0 +1,260 @@
+use crate::config::Config;
+use crate::ide_project::{collect_sources, find_project_file, relative_path, root_prefix};
+use makefile_parser::MakefileConfig;
+use regex::Regex;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// IAR 工程文件的搜索目录：项目根目录和 EWARM 目录
+const SEARCH_DIRS: [&str; 2] = [".", "EWARM"];
+
+/// 查找 IAR 工程，优先取 .eww 工作区引用的全部 .ewp，没有工作区时取找到的 .ewp
+pub fn find_ewp(root: &Path) -> Vec<PathBuf> {
+    if let Some(eww) = find_project_file(root, &SEARCH_DIRS, "eww") {
+        info!("Found `{}`", eww.display());
+        match fs::read_to_string(&eww) {
+            Ok(content) => {
+                let projects = eww_projects(&eww, &content);
+                if !projects.is_empty() {
+                    return projects;
+                }
+                warn!("No existing `.ewp` referenced by {}", eww.display());
+            }
+            Err(e) => warn!("Failed to read {}: {}", eww.display(), e),
+        }
+    }
+    find_project_file(root, &SEARCH_DIRS, "ewp")
+        .into_iter()
+        .collect()
+}
+
+/// 解析 .eww 中 `<project><path>` 引用的工程文件，`$WS_DIR$` 为工作区所在目录，忽略不存在的文件
+fn eww_projects(eww: &Path, content: &str) -> Vec<PathBuf> {
+    let ws_dir = eww.parent().unwrap_or(Path::new(""));
+    let re_path = Regex::new(r"(?s)<project>\s*<path>(.*?)</path>").unwrap();
+    re_path
+        .captures_iter(content)
+        .map(|caps| {
+            let path = caps[1].trim().replace('\\', "/");
+            match path.strip_prefix("$WS_DIR$/") {
+                Some(path) => ws_dir.join(path),
+                None => PathBuf::from(path),
+            }
+        })
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+/// 向每个配置的指定选项追加缺少的 `<state>`，如 CCDefines、CCIncludePath2
+fn add_states(content: &str, option: &str, states: &[String]) -> String {
+    let re_option = Regex::new(&format!(
+        r"(?s)(<name>{}</name>.*?)(\r?\n[ \t]*)(</option>)",
+        regex::escape(option)
+    ))
+    .unwrap();
+    re_option
+        .replace_all(content, |caps: &regex::Captures| {
+            let mut block = caps[1].to_string();
+            for state in states {
+                let state = format!("<state>{state}</state>");
+                if !block.contains(&state) {
+                    block.push_str(&format!("{}    {}", &caps[2], state));
+                }
+            }
+            format!("{}{}{}", block, &caps[2], &caps[3])
+        })
+        .into_owned()
+}
+
+/// 向 IAR 工程注入用户代码头文件路径与源文件分组，每个用户代码顶层目录一个分组，
+/// 有 Makefile 时一并同步其中的头文件路径与宏定义，返回工程文件是否被修改
+pub fn iar_custom_init(
+    root: &Path,
+    config: &Config,
+    project_file: &Path,
+    build: Option<&MakefileConfig>,
+    dry_run: bool,
+) -> io::Result<bool> {
+    let original = fs::read_to_string(project_file)?;
+
+    // 工程文件中的路径相对于 $PROJ_DIR$
+    let prefix = format!("$PROJ_DIR$/{}", root_prefix(root, project_file));
+    let user_code_dirs = config.user_code_dirs();
+
+    // 头文件路径与宏定义，每个配置各有一份 CCIncludePath2 与 CCDefines
+    let mut includes: Vec<String> = Vec::new();
+    let mut defines: Vec<String> = Vec::new();
+    if let Some(build) = build {
+        includes.extend(build.includes.iter().map(|include| {
+            if Path::new(include).is_absolute() {
+                include.clone()
+            } else {
+                format!("{prefix}{}", include.trim_start_matches("./"))
+            }
+        }));
+        defines.extend(build.defines.iter().cloned());
+    }
+    includes.extend(user_code_dirs.iter().map(|dir| format!("{prefix}{dir}")));
+    let content = add_states(&original, "CCIncludePath2", &includes);
+    let mut content = add_states(&content, "CCDefines", &defines);
+
+    // 源文件分组，已存在时重新生成
+    let mut groups = String::new();
+    let mut count = 0;
+    for dir in &user_code_dirs {
+        let sources = collect_sources(&root.join(dir))?;
+        count += sources.len();
+        groups.push_str(&format!("\n    <group>\n        <name>{dir}</name>"));
+        for source in &sources {
+            groups.push_str(&format!(
+                "\n        <file>\n            <name>{}{}</name>\n        </file>",
+                prefix,
+                relative_path(root, source)
+            ));
+        }
+        groups.push_str("\n    </group>");
+
+        let re_group = Regex::new(&format!(
+            r"(?s)\s*<group>\s*<name>{}</name>.*?</group>",
+            regex::escape(dir)
+        ))
+        .unwrap();
+        content = re_group.replace_all(&content, "").into_owned();
+    }
+    let re_project = Regex::new(r"(\r?\n)</project>").unwrap();
+    if !re_project.is_match(&content) {
+        warn!(
+            "No `</project>` found in {}, skipping",
+            project_file.display()
+        );
+        return Ok(false);
+    }
+    let content = re_project.replace(&content, |caps: &regex::Captures| {
+        format!("{}{}</project>", groups, &caps[1])
+    });
+    if content == original {
+        return Ok(false);
+    }
+
+    if dry_run {
+        info!(
+            "[dry-run] Would add {} source files to groups {} in {}",
+            count,
+            user_code_dirs.join(", "),
+            project_file.display()
+        );
+        return Ok(true);
+    }
+    fs::write(project_file, content.as_bytes())?;
+    info!(
+        "Added {} source files to groups {} in {}",
+        count,
+        user_code_dirs.join(", "),
+        project_file.display()
+    );
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EWP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<project>
+    <configuration>
+        <name>demo</name>
+        <settings>
+            <name>ICCARM</name>
+            <data>
+                <option>
+                    <name>CCDefines</name>
+                    <state>USE_HAL_DRIVER</state>
+                </option>
+                <option>
+                    <name>CCIncludePath2</name>
+                    <state>$PROJ_DIR$/../Core/Inc</state>
+                </option>
+            </data>
+        </settings>
+    </configuration>
+    <group>
+        <name>Application</name>
+    </group>
+</project>
+"#;
+
+    const EWW: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<workspace>
+    <project>
+        <path>$WS_DIR$\demo.ewp</path>
+    </project>
+    <batchBuild/>
+</workspace>
+"#;
+
+    fn fixture() -> (tempfile::TempDir, PathBuf) {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("UserCode/app")).unwrap();
+        fs::create_dir_all(root.path().join("EWARM")).unwrap();
+        fs::write(root.path().join("UserCode/app/app.c"), "").unwrap();
+        let project_file = root.path().join("EWARM/demo.ewp");
+        fs::write(&project_file, EWP).unwrap();
+        (root, project_file)
+    }
+
+    #[test]
+    fn makefile_defines_and_includes_are_added() {
+        let (root, project_file) = fixture();
+        let build = makefile_parser::parse_makefile(
+            "C_DEFS = -DUSE_HAL_DRIVER -DSTM32F407xx\nC_INCLUDES = -ICore/Inc -IDrivers/CMSIS/Include\n",
+        );
+        let config = Config::default();
+        assert!(iar_custom_init(root.path(), &config, &project_file, Some(&build), false).unwrap());
+
+        let content = fs::read_to_string(&project_file).unwrap();
+        assert_eq!(content.matches("<state>USE_HAL_DRIVER</state>").count(), 1);
+        assert!(content.contains("<state>STM32F407xx</state>"));
+        assert_eq!(
+            content
+                .matches("<state>$PROJ_DIR$/../Core/Inc</state>")
+                .count(),
+            1
+        );
+        assert!(content.contains("<state>$PROJ_DIR$/../Drivers/CMSIS/Include</state>"));
+        assert!(content.contains("<state>$PROJ_DIR$/../UserCode</state>"));
+        assert!(content.contains("<name>$PROJ_DIR$/../UserCode/app/app.c</name>"));
+
+        // 再次运行时分组被替换而不是重复添加
+        assert!(
+            !iar_custom_init(root.path(), &config, &project_file, Some(&build), false).unwrap()
+        );
+        assert_eq!(fs::read_to_string(&project_file).unwrap(), content);
+        assert_eq!(content.matches("<name>UserCode</name>").count(), 1);
+    }
+
+    #[test]
+    fn configured_directories_become_groups() {
+        let (root, project_file) = fixture();
+        fs::create_dir_all(root.path().join("App")).unwrap();
+        fs::write(root.path().join("App/app.c"), "").unwrap();
+        let config = Config {
+            directories: vec!["App".to_string()],
+            ..Config::default()
+        };
+        assert!(iar_custom_init(root.path(), &config, &project_file, None, false).unwrap());
+        let content = fs::read_to_string(&project_file).unwrap();
+        assert!(content.contains("<state>$PROJ_DIR$/../App</state>"));
+        assert!(content.contains("<name>App</name>"));
+        assert!(content.contains("<name>$PROJ_DIR$/../App/app.c</name>"));
+    }
+
+    #[test]
+    fn workspace_references_are_followed() {
+        let (root, project_file) = fixture();
+        fs::write(root.path().join("EWARM/other.ewp"), EWP).unwrap();
+        fs::write(root.path().join("EWARM/demo.eww"), EWW).unwrap();
+        assert_eq!(find_ewp(root.path()), [project_file]);
+    }
+}
@@ -0,0 +1,67 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// 依次在项目根目录下的各目录中查找指定扩展名的工程文件
+pub fn find_project_file(root: &Path, dirs: &[&str], extension: &str) -> Option<PathBuf> {
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(root.join(dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == extension) {
+                return Some(path);
+            }
+        }
+    }
+    None
+}
+
+/// 是否为 IDE 可编译的源文件：C、汇编与 C++
+pub fn is_source(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| matches!(ext, "c" | "s" | "S" | "cpp" | "cc" | "cxx"))
+}
+
+/// 递归收集目录下的源文件并排序，目录不存在时为空
+pub fn collect_sources(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fn walk(dir: &Path, sources: &mut Vec<PathBuf>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(&path, sources)?;
+            } else if is_source(&path) {
+                sources.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut sources = Vec::new();
+    if dir.is_dir() {
+        walk(dir, &mut sources)?;
+    }
+    sources.sort();
+    Ok(sources)
+}
+
+/// 从工程文件所在目录回到项目根目录的相对路径前缀，如 `../`
+pub fn root_prefix(root: &Path, project_file: &Path) -> String {
+    let depth = project_file
+        .strip_prefix(root)
+        .unwrap_or(project_file)
+        .parent()
+        .map(|p| p.components().filter(|c| c.as_os_str() != ".").count())
+        .unwrap_or(0);
+    "../".repeat(depth)
+}
+
+/// 相对于项目根目录的路径，统一使用 `/` 分隔
+pub fn relative_path(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
@@ -0,0 +1,201 @@
+use crate::config::Config;
+use crate::ide_project::{collect_sources, find_project_file, relative_path, root_prefix};
+use regex::Regex;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// 查找 Keil 工程文件，依次搜索项目根目录和 MDK-ARM 目录
+pub fn find_uvprojx(root: &Path) -> Option<PathBuf> {
+    find_project_file(root, &[".", "MDK-ARM"], "uvprojx")
+}
+
+/// Keil 的文件类型：1 为 C，2 为汇编，8 为 C++
+fn get_file_type(path: &Path) -> Option<u8> {
+    match path.extension()?.to_str()? {
+        "c" => Some(1),
+        "s" | "S" => Some(2),
+        "cpp" | "cc" | "cxx" => Some(8),
+        _ => None,
+    }
+}
+
+/// 向 Keil 工程注入用户代码头文件路径与源文件分组，每个用户代码顶层目录一个分组，
+/// 返回工程文件是否被修改
+pub fn keil_custom_init(
+    root: &Path,
+    config: &Config,
+    project_file: &Path,
+    dry_run: bool,
+) -> io::Result<bool> {
+    let original = fs::read_to_string(project_file)?;
+
+    // 工程文件中的路径相对于工程文件所在目录
+    let prefix = root_prefix(root, project_file);
+    let user_code_dirs = config.user_code_dirs();
+
+    // 头文件路径
+    let re_include = Regex::new(r"(?s)(<Cads>.*?<IncludePath>)(.*?)(</IncludePath>)").unwrap();
+    let content = re_include.replace_all(&original, |caps: &regex::Captures| {
+        let mut paths = caps[2].to_string();
+        for dir in &user_code_dirs {
+            let include = format!("{prefix}{dir}");
+            if paths.split(';').any(|p| p.trim() == include) {
+                continue;
+            }
+            if !paths.trim().is_empty() {
+                paths.push(';');
+            }
+            paths.push_str(&include);
+        }
+        format!("{}{}{}", &caps[1], paths, &caps[3])
+    });
+
+    // 源文件分组，已存在时重新生成
+    let mut groups = String::new();
+    let mut count = 0;
+    let mut content = content.into_owned();
+    for dir in &user_code_dirs {
+        let sources = collect_sources(&root.join(dir))?;
+        count += sources.len();
+        groups.push_str(&format!(
+            "\n        <Group>\n          <GroupName>{dir}</GroupName>\n          <Files>"
+        ));
+        for source in &sources {
+            let path = relative_path(root, source);
+            groups.push_str(&format!(
+                "\n            <File>\n              <FileName>{}</FileName>\n              <FileType>{}</FileType>\n              <FilePath>{}{}</FilePath>\n            </File>",
+                source.file_name().unwrap_or_default().to_string_lossy(),
+                get_file_type(source).unwrap_or(1),
+                prefix,
+                path
+            ));
+        }
+        groups.push_str("\n          </Files>\n        </Group>");
+
+        let re_group = Regex::new(&format!(
+            r"(?s)\s*<Group>\s*<GroupName>{}</GroupName>.*?</Group>",
+            regex::escape(dir)
+        ))
+        .unwrap();
+        content = re_group.replace_all(&content, "").into_owned();
+    }
+    let re_groups = Regex::new(r"(\r?\n[ \t]*)</Groups>").unwrap();
+    if !re_groups.is_match(&content) {
+        warn!(
+            "No `<Groups>` found in {}, skipping",
+            project_file.display()
+        );
+        return Ok(false);
+    }
+    let content = re_groups.replace_all(&content, |caps: &regex::Captures| {
+        format!("{}{}</Groups>", groups, &caps[1])
+    });
+    if content == original {
+        return Ok(false);
+    }
+
+    if dry_run {
+        info!(
+            "[dry-run] Would add {} source files to groups {} in {}",
+            count,
+            user_code_dirs.join(", "),
+            project_file.display()
+        );
+        return Ok(true);
+    }
+    fs::write(project_file, content.as_bytes())?;
+    info!(
+        "Added {} source files to groups {} in {}",
+        count,
+        user_code_dirs.join(", "),
+        project_file.display()
+    );
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UVPROJX: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no" ?>
+<Project>
+  <Targets>
+    <Target>
+      <TargetOption>
+        <TargetArmAds>
+          <Cads>
+            <VariousControls>
+              <IncludePath>../Core/Inc</IncludePath>
+            </VariousControls>
+          </Cads>
+        </TargetArmAds>
+      </TargetOption>
+      <Groups>
+        <Group>
+          <GroupName>Application/User/Core</GroupName>
+        </Group>
+      </Groups>
+    </Target>
+  </Targets>
+</Project>
+"#;
+
+    fn fixture(uvprojx: &str) -> (tempfile::TempDir, PathBuf) {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("UserCode/app")).unwrap();
+        fs::create_dir_all(root.path().join("MDK-ARM")).unwrap();
+        fs::write(root.path().join("UserCode/app/app.c"), "").unwrap();
+        let project_file = root.path().join("MDK-ARM/demo.uvprojx");
+        fs::write(&project_file, uvprojx).unwrap();
+        (root, project_file)
+    }
+
+    #[test]
+    fn rerun_replaces_group() {
+        let (root, project_file) = fixture(UVPROJX);
+        let config = Config::default();
+        assert!(keil_custom_init(root.path(), &config, &project_file, false).unwrap());
+        let first = fs::read_to_string(&project_file).unwrap();
+        assert!(first.contains("<IncludePath>../Core/Inc;../UserCode</IncludePath>"));
+        assert!(first.contains("<FilePath>../UserCode/app/app.c</FilePath>"));
+
+        fs::write(root.path().join("UserCode/app/main.cpp"), "").unwrap();
+        assert!(keil_custom_init(root.path(), &config, &project_file, false).unwrap());
+        let second = fs::read_to_string(&project_file).unwrap();
+        assert_eq!(second.matches("<GroupName>UserCode</GroupName>").count(), 1);
+        assert_eq!(second.matches("../UserCode</IncludePath>").count(), 1);
+        assert!(second.contains("<FilePath>../UserCode/app/main.cpp</FilePath>"));
+
+        assert!(!keil_custom_init(root.path(), &config, &project_file, false).unwrap());
+        assert_eq!(fs::read_to_string(&project_file).unwrap(), second);
+    }
+
+    #[test]
+    fn configured_directories_become_groups() {
+        let (root, project_file) = fixture(UVPROJX);
+        fs::create_dir_all(root.path().join("App")).unwrap();
+        fs::write(root.path().join("App/app.c"), "").unwrap();
+        let config = Config {
+            directories: vec!["App".to_string()],
+            ..Config::default()
+        };
+        assert!(keil_custom_init(root.path(), &config, &project_file, false).unwrap());
+        let content = fs::read_to_string(&project_file).unwrap();
+        assert!(content.contains("<IncludePath>../Core/Inc;../App;../UserCode</IncludePath>"));
+        assert!(content.contains("<GroupName>App</GroupName>"));
+        assert!(content.contains("<FilePath>../App/app.c</FilePath>"));
+    }
+
+    #[test]
+    fn missing_groups_is_skipped() {
+        let uvprojx = UVPROJX.replace(
+            "      <Groups>\n        <Group>\n          <GroupName>Application/User/Core</GroupName>\n        </Group>\n      </Groups>\n",
+            "",
+        );
+        let (root, project_file) = fixture(&uvprojx);
+        assert!(!keil_custom_init(root.path(), &Config::default(), &project_file, false).unwrap());
+        assert_eq!(fs::read_to_string(&project_file).unwrap(), uvprojx);
+    }
+}
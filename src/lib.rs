@@ -0,0 +1,2574 @@
+mod boards;
+mod compile_commands;
+mod config;
+mod contexts;
+mod generate_gitignore;
+mod iar;
+mod ide_project;
+mod keil;
+mod manifest;
+mod patches;
+mod render;
+mod report;
+mod stm32cubemx;
+mod templates;
+mod utils;
+
+pub use crate::report::{InitReport, SizeReport};
+pub use crate::stm32cubemx::{CubeMxError, Toolchain};
+
+use crate::boards::{find_board, BOARDS, DEFAULT_MCU};
+use crate::compile_commands::{find_compile_commands, load_compile_flags};
+use crate::config::Config;
+use crate::contexts::{
+    ClangFormatContext, ClangdContext, CppPropertiesContext, CreateContext, DoxygenContext,
+    DriverContext, EIDEConfigContext, EIDETargetContext, LaunchContext, OpenOCDContext,
+    ReadmeContext, TasksContext,
+};
+use crate::generate_gitignore::generate_gitignore;
+use crate::iar::{find_ewp, iar_custom_init};
+use crate::keil::{find_uvprojx, keil_custom_init};
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::patches::{apply_patch, get_file, in_dir, revert_patch, Patch, PatchStatus};
+use crate::render::{render_file, render_string, write_file};
+use crate::stm32cubemx::{
+    check_stm32cubemx, generate_code, get_ioc_part_number, get_ioc_peripherals, get_ioc_value,
+    get_toolchain, has_fpu, run_script, script_path, DEFAULT_CUBEMX_RETRIES,
+    DEFAULT_CUBEMX_TIMEOUT,
+};
+use crate::templates::{
+    APP_C, APP_H, CLANGD, CLANG_FORMAT, CLANG_FORMAT_BASED_ON, CREATE_PROJECT_CMD1,
+    CREATE_PROJECT_CMD2, C_CPP_PROPERTIES, DOXYFILE, DRIVER_C, DRIVER_H, EIDE_CONFIG,
+    EIDE_WORKSPACE, GITATTRIBUTES, OPENOCD_CFG, PRE_COMMIT_HOOK, README_MD, VSCODE_LAUNCH,
+    VSCODE_TASKS,
+};
+use crate::utils::{get_author, get_email, log_dry_run_diff};
+use anyhow::anyhow;
+use chrono::Local;
+use clap::ValueEnum;
+use dialoguer::{Confirm, MultiSelect, Select};
+use makefile_parser::MakefileConfig;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+use std::{env, fs};
+use tracing::{error, info, warn};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum FPUType {
+    #[default]
+    Hard,
+    Soft,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IDEType {
+    Clion,
+    Eide,
+    Iar,
+    Keil,
+    None,
+}
+
+/// 生成的 .clang-format 风格
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ClangFormatStyle {
+    /// 内置的团队风格
+    #[default]
+    Default,
+    Llvm,
+    Google,
+    Mozilla,
+    Webkit,
+    /// 复制 --template-dir 中的 .clang-format
+    Custom,
+}
+
+impl ClangFormatStyle {
+    /// clang-format 的 BasedOnStyle，内置与自定义风格为 None
+    fn based_on_style(self) -> Option<&'static str> {
+        match self {
+            ClangFormatStyle::Llvm => Some("LLVM"),
+            ClangFormatStyle::Google => Some("Google"),
+            ClangFormatStyle::Mozilla => Some("Mozilla"),
+            ClangFormatStyle::Webkit => Some("WebKit"),
+            ClangFormatStyle::Default | ClangFormatStyle::Custom => None,
+        }
+    }
+}
+
+/// 调试器类型
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Probe {
+    #[default]
+    Stlink,
+    Jlink,
+    CmsisDap,
+}
+
+impl Probe {
+    /// OpenOCD 的 interface 脚本名
+    fn openocd_interface(self) -> &'static str {
+        match self {
+            Probe::Stlink => "stlink",
+            Probe::Jlink => "jlink",
+            Probe::CmsisDap => "cmsis-dap",
+        }
+    }
+
+    /// cortex-debug 的 servertype，CMSIS-DAP 通过 OpenOCD 调试
+    fn servertype(self) -> &'static str {
+        match self {
+            Probe::Stlink => "stlink",
+            Probe::Jlink => "jlink",
+            Probe::CmsisDap => "openocd",
+        }
+    }
+
+    fn display_name(self) -> &'static str {
+        match self {
+            Probe::Stlink => "ST-Link",
+            Probe::Jlink => "J-Link",
+            Probe::CmsisDap => "CMSIS-DAP",
+        }
+    }
+}
+
+/// EIDE 的烧录方式
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Flasher {
+    #[default]
+    Stlink,
+    Openocd,
+}
+
+/// 初始化项目的选项
+#[derive(Debug, Default, Clone)]
+pub struct InitOptions {
+    /// 项目根目录，为空时使用当前目录
+    pub project_dir: PathBuf,
+    /// 跳过生成 UserCode 目录结构
+    pub skip_generate_user_code: bool,
+    /// 跳过生成 .clang-format
+    pub skip_generate_clang_format: bool,
+    /// .clang-format 的风格
+    pub clang_format_style: ClangFormatStyle,
+    /// 自定义模板目录，`ClangFormatStyle::Custom` 时从中复制 .clang-format
+    pub template_dir: Option<PathBuf>,
+    /// .gitignore 已存在时合并缺少的规则
+    pub merge_gitignore: bool,
+    /// 跳过生成 .gitattributes
+    pub skip_gitattributes: bool,
+    /// 跳过非侵入式头文件配置
+    pub skip_non_intrusive_headers: bool,
+    /// 修改已有文件前显示变更并确认，--force 或非交互终端时不询问
+    pub interactive_patch: bool,
+    /// 覆盖 git 配置中的作者
+    pub author: Option<String>,
+    /// 覆盖 git 配置中的邮箱
+    pub email: Option<String>,
+    /// FPU 类型
+    pub fpu: FPUType,
+    /// 强制重新生成
+    pub force: bool,
+    /// IDE 类型，为 None 时交互式选择
+    pub ide: Option<IDEType>,
+    /// 跳过生成 openocd.cfg
+    pub no_openocd: bool,
+    /// openocd.cfg 与 launch.json 使用的调试器
+    pub probe: Probe,
+    /// EIDE 的烧录方式
+    pub flasher: Flasher,
+    /// 只输出将要执行的操作，不实际修改文件
+    pub dry_run: bool,
+    /// 打补丁前备份为 `<file>.bak`
+    pub backup: bool,
+    /// 根据 Makefile 生成 .clangd
+    pub clangd: bool,
+    /// 根据 Makefile 生成 .vscode/c_cpp_properties.json
+    pub cpp_properties: bool,
+    /// 生成 Doxyfile 与 docs 输出目录
+    pub doxygen: bool,
+    /// 等待 STM32CubeMX 的超时时间（秒），为 None 时使用默认值
+    pub cubemx_timeout: Option<u64>,
+    /// STM32CubeMX 生成代码失败时的重试次数，为 None 时使用默认值
+    pub cubemx_retries: Option<u32>,
+    /// STM32CubeMX 是否为每个外设生成单独的 .c/.h 文件
+    pub couple_files: bool,
+    /// git 初始分支名，为 None 时使用 git 的默认配置
+    pub branch: Option<String>,
+    /// 跳过 git init 与初始提交
+    pub skip_git: bool,
+    /// 为 Makefile 项目生成 .vscode/tasks.json
+    pub vscode_tasks: bool,
+    /// 安装检查 clang-format 的 pre-commit hook
+    pub install_hooks: bool,
+    /// 向 CMake 工具链文件追加 UserCode 头文件路径
+    pub patch_toolchain: bool,
+    /// 追加到 CMake 工具链文件的额外编译参数，设置时同样会修改工具链文件
+    pub toolchain_flags: Option<String>,
+    /// 根据 .ioc 中启用的外设生成驱动桩文件
+    pub scaffold_drivers: bool,
+    /// 要生成的 UserCode 目录，按目录名或完整路径匹配，为 None 时交互选择或全部生成
+    pub dirs: Option<Vec<String>>,
+}
+
+/// 创建项目的选项
+#[derive(Debug, Clone)]
+pub struct CreateOptions {
+    /// 项目名
+    pub project_name: String,
+    /// 使用的工具链
+    pub toolchain: Toolchain,
+    /// 是否在创建后立即初始化项目
+    pub run_init: bool,
+    /// 外部高速晶振频率 (Hz)
+    pub hse_value: u32,
+    /// 开发板名，设置时使用开发板的芯片和晶振频率，忽略 `hse_value`
+    pub board: Option<String>,
+    /// 在该目录下创建项目目录，为空时使用当前目录
+    pub output_dir: PathBuf,
+    /// 在 RCC.HSE_VALUE 之后写入 .ioc 的配置项，同名时覆盖前者
+    pub ioc_set: Vec<(String, String)>,
+    /// 项目目录已存在时允许删除其中未提交的修改，非交互运行时不询问直接重新生成
+    pub allow_dirty: bool,
+    /// 初始化项目的选项
+    pub init_options: InitOptions,
+}
+
+#[derive(Serialize)]
+struct InitContext {
+    author: String,
+    email: String,
+    date: String,
+    year: String,
+}
+
+/// 初始化 STM32 项目，返回生成和修改的文件
+pub fn init_project(opts: InitOptions) -> anyhow::Result<InitReport> {
+    let InitOptions {
+        project_dir,
+        skip_generate_user_code,
+        skip_generate_clang_format,
+        clang_format_style,
+        template_dir,
+        merge_gitignore,
+        skip_gitattributes,
+        skip_non_intrusive_headers,
+        interactive_patch,
+        author,
+        email,
+        fpu,
+        force,
+        ide,
+        no_openocd,
+        probe,
+        flasher,
+        dry_run,
+        backup,
+        mut clangd,
+        cpp_properties,
+        doxygen,
+        cubemx_timeout,
+        cubemx_retries,
+        couple_files,
+        branch,
+        skip_git,
+        vscode_tasks,
+        install_hooks,
+        patch_toolchain,
+        toolchain_flags,
+        scaffold_drivers,
+        dirs,
+    } = opts;
+    let cubemx_timeout = Duration::from_secs(cubemx_timeout.unwrap_or(DEFAULT_CUBEMX_TIMEOUT));
+    let cubemx_retries = cubemx_retries.unwrap_or(DEFAULT_CUBEMX_RETRIES);
+    let confirm = interactive_patch && !force && !dry_run && io::stdin().is_terminal();
+
+    let root = project_dir.as_path();
+    if !root.as_os_str().is_empty() && !root.is_dir() {
+        return Err(anyhow!(
+            "Project directory {} does not exist",
+            root.display()
+        ));
+    }
+    // `;` 在 CMake 中是列表分隔符，写入编译参数后会拆开命令行
+    if let Some(flags) = &toolchain_flags
+        && flags.contains(';')
+    {
+        return Err(anyhow!("--toolchain-flags must not contain `;`: {}", flags));
+    }
+    let config = Config::load(root);
+    let mut report = InitReport {
+        dry_run,
+        ..Default::default()
+    };
+    let existing = top_level_entries(root);
+
+    // 渲染上下文
+    let author = author.unwrap_or_else(get_author);
+    let email = email.unwrap_or_else(get_email);
+
+    let now = Local::now();
+    let ctx = InitContext {
+        author,
+        email,
+        date: now.format("%Y-%m-%d").to_string(),
+        year: now.format("%Y").to_string(),
+    };
+
+    // 在修改任何文件前确定要生成的目录，指定了未知目录时直接报错
+    // 已有用户代码时不再询问，补齐配置中缺少的目录，已有文件保持不变
+    let has_user_code = root.join(config.app_dir()).is_dir();
+    let directories = if skip_generate_user_code {
+        Vec::new()
+    } else {
+        if has_user_code && dirs.is_none() {
+            info!(
+                "Found existing user code in {}, only adding missing directories",
+                config.app_dir()
+            );
+        }
+        // 与是否选择 IDE 无关，终端中运行且未指定 --dirs 时总是询问
+        let interactive = io::stdin().is_terminal() && !has_user_code;
+        select_directories(&config, dirs.as_deref(), interactive)?
+    };
+
+    // 自定义的 .clang-format 在修改任何文件前读取，不存在时直接报错
+    let custom_clang_format = match clang_format_style {
+        ClangFormatStyle::Custom if !skip_generate_clang_format => {
+            let dir = template_dir
+                .as_deref()
+                .ok_or_else(|| anyhow!("Custom clang-format style requires a template dir"))?;
+            let source = dir.join(".clang-format");
+            let content = fs::read_to_string(&source)
+                .map_err(|e| anyhow!("Failed to read {}: {}", source.display(), e))?;
+            Some(content)
+        }
+        _ => None,
+    };
+
+    // 已在上级目录的 git 仓库中时不再嵌套创建仓库
+    let use_git = if skip_git {
+        info!("Skipping git initialization");
+        false
+    } else if let Some(toplevel) = parent_work_tree(root) {
+        warn!(
+            "Already inside git work tree {}, skipping git initialization",
+            toplevel.display()
+        );
+        false
+    } else {
+        true
+    };
+
+    // 初始化项目配置
+    if use_git {
+        if dry_run {
+            info!("[dry-run] Would initialize git repository");
+        } else {
+            git_init(root, branch.as_deref());
+        }
+    }
+    info!("Generating .gitignore file...");
+    let gitignore = root_path(root, ".gitignore");
+    let written = generate_gitignore(
+        &gitignore,
+        None,
+        &makefile_build_dir(root),
+        force,
+        merge_gitignore,
+        dry_run,
+    )?;
+    report.file(&gitignore, written);
+
+    if !skip_gitattributes {
+        info!("Generating .gitattributes file...");
+        let path = root_path(root, ".gitattributes");
+        let written = render_file(&path, GITATTRIBUTES, &ctx, force, dry_run)?;
+        report.file(&path, written);
+    }
+
+    if !skip_generate_clang_format {
+        info!("Generating .clang-format file");
+        let path = root_path(root, ".clang-format");
+        let written = match (&custom_clang_format, clang_format_style.based_on_style()) {
+            (Some(content), _) => write_file(&path, content, force, dry_run)?,
+            (None, Some(style)) => {
+                let ctx = ClangFormatContext { style };
+                render_file(&path, CLANG_FORMAT_BASED_ON, &ctx, force, dry_run)?
+            }
+            (None, None) => render_file(&path, CLANG_FORMAT, &ctx, force, dry_run)?,
+        };
+        report.file(&path, written);
+    }
+
+    if !skip_generate_user_code {
+        info!("Generating user code directories...");
+        let missing = directories
+            .iter()
+            .filter(|dir| !root.join(dir).is_dir())
+            .count();
+        if missing == 0 {
+            info!("All user code directories already exist");
+        }
+        for dir in &directories {
+            let dir = root_path(root, dir);
+            if Path::new(&dir).is_dir() {
+                continue;
+            }
+            report.created.push(dir.clone());
+            if dry_run {
+                info!("[dry-run] Would create dir {}", dir);
+                continue;
+            }
+            fs::create_dir_all(&dir)?;
+            info!("Created dir {}", dir);
+        }
+        let app_dir = root_path(root, config.app_dir());
+        if dry_run {
+            info!("[dry-run] Would create dir {}", app_dir);
+        } else {
+            fs::create_dir_all(&app_dir)?;
+        }
+        for (path, template) in [
+            (format!("{app_dir}/app.h"), APP_H),
+            (format!("{app_dir}/app.c"), APP_C),
+        ] {
+            let written = render_file(&path, template, &ctx, force, dry_run)?;
+            report.file(&path, written);
+        }
+        let readme = config.readme_path();
+        let readme_ctx = ReadmeContext {
+            directories: &directory_tree(&directories, config.app_dir(), &readme),
+        };
+        let readme = root_path(root, &readme);
+        let written = render_file(&readme, README_MD, &readme_ctx, force, dry_run)?;
+        report.file(&readme, written);
+    }
+
+    if !skip_non_intrusive_headers {
+        if skip_generate_user_code {
+            info!("Skipping non-intrusive headers due to skip_generate_user_code");
+        } else {
+            info!("Generating non-intrusive headers");
+            for patch in non_intrusive_header_patches(CMakeLists::detect(root), config.app_dir()) {
+                let patch = in_dir(patch, root);
+                apply_and_report(&patch, dry_run, backup, confirm, &mut report)?;
+            }
+        }
+    }
+
+    if scaffold_drivers {
+        info!("Generating driver stubs...");
+        let drivers_dir = root_path(root, config.drivers_dir());
+        scaffold_driver_stubs(root, &drivers_dir, &ctx, force, dry_run, &mut report)?;
+    }
+
+    let has_makefile = root.join("Makefile").exists();
+    let fpu = check_fpu_support(root, fpu);
+    if has_makefile {
+        check_fpu_type(root, fpu)?;
+    }
+    let cmake_lists = CMakeLists::detect(root);
+    match cmake_lists {
+        Some(CMakeLists::Template) => {
+            if root.join(CMakeLists::Plain.file_name()).exists() {
+                info!(
+                    "Found both `CMakeLists_template.txt` and `CMakeLists.txt`, using the template"
+                );
+            }
+            info!("Found `CMakeLists_template.txt`, initializing CLion project...");
+            clion_custom_init(root, fpu, dry_run, backup, confirm, &mut report)?;
+            regenerate_code(root, dry_run, cubemx_timeout, cubemx_retries, couple_files);
+        }
+        Some(CMakeLists::Plain) => {
+            info!("Found `CMakeLists.txt`, initializing CMake project...");
+            for patch in cmake_lists_patches() {
+                let patch = in_dir(patch, root);
+                apply_and_report(&patch, dry_run, backup, confirm, &mut report)?;
+            }
+        }
+        None => {}
+    }
+    let cmake_presets = root_path(root, "CMakePresets.json");
+    if Path::new(&cmake_presets).exists() {
+        info!("Found `CMakePresets.json`, initializing CMake presets...");
+        let changed = cmake_presets_init(&cmake_presets, dry_run)?;
+        report.patch(&cmake_presets, changed);
+    }
+    if (patch_toolchain || toolchain_flags.is_some()) && root.join(CMAKE_TOOLCHAIN_FILE).exists() {
+        info!(
+            "Found `{}`, patching compiler flags...",
+            CMAKE_TOOLCHAIN_FILE
+        );
+        let patch = in_dir(cmake_toolchain_patch(toolchain_flags.as_deref()), root);
+        apply_and_report(&patch, dry_run, backup, confirm, &mut report)?;
+    }
+    let uvprojx = find_uvprojx(root);
+    if let Some(uvprojx) = &uvprojx {
+        info!("Found `{}`", uvprojx.display());
+    }
+    let ewp = find_ewp(root);
+    for ewp in &ewp {
+        info!("Found `{}`", ewp.display());
+    }
+    if has_makefile || uvprojx.is_some() || !ewp.is_empty() {
+        info!("Initializing Makefile/MDK-ARM/EWARM project...");
+        let ide = match ide {
+            Some(ide) => ide,
+            None if !io::stdin().is_terminal() => {
+                info!("stdin is not a terminal, skipping IDE selection");
+                IDEType::None
+            }
+            None => {
+                // 菜单项、对应的 IDE 以及是否生成 .clangd
+                let choices = [
+                    ("VSCode + EIDE", IDEType::Eide, false),
+                    ("Keil MDK-ARM", IDEType::Keil, false),
+                    ("IAR EWARM", IDEType::Iar, false),
+                    ("Makefile + clangd only", IDEType::None, true),
+                    ("None", IDEType::None, false),
+                ];
+                let choice = Select::new()
+                    .with_prompt("Choose your ide")
+                    .items(choices.iter().map(|(label, _, _)| label))
+                    .default(0)
+                    .interact()?;
+                let (_, ide, clangd_only) = choices[choice];
+                clangd |= clangd_only;
+                ide
+            }
+        };
+        report.ide = Some(ide);
+        match ide {
+            IDEType::Eide if !has_makefile => {
+                warn!("EIDE initialization requires `Makefile`, skipping");
+            }
+            IDEType::Eide => {
+                eide_custom_init(root, &config, force, dry_run, probe, flasher, &mut report)?
+            }
+            IDEType::Keil => match &uvprojx {
+                Some(uvprojx) => {
+                    let changed = keil_custom_init(root, &config, uvprojx, dry_run)?;
+                    report.patch(&uvprojx.to_string_lossy(), changed);
+                }
+                None => warn!("Keil initialization requires a `.uvprojx` file, skipping"),
+            },
+            IDEType::Iar if ewp.is_empty() => {
+                warn!("IAR initialization requires a `.ewp` file, skipping");
+            }
+            IDEType::Iar => {
+                // 有 Makefile 时沿用其中的头文件路径与宏定义，否则保留 .ewp 中已有的设置
+                let build = match load_build_config(root) {
+                    Ok(build) => Some(build),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                    Err(e) => return Err(e.into()),
+                };
+                for ewp in &ewp {
+                    let changed = iar_custom_init(root, &config, ewp, build.as_ref(), dry_run)?;
+                    report.patch(&ewp.to_string_lossy(), changed);
+                }
+            }
+            // CMake 工程的补丁已在上面应用
+            IDEType::Clion if cmake_lists.is_some() => {}
+            IDEType::Clion => {
+                warn!("CLion initialization requires `CMakeLists_template.txt` or `CMakeLists.txt`, skipping");
+            }
+            IDEType::None => {
+                warn!("--");
+            }
+        }
+        if vscode_tasks {
+            if has_makefile {
+                vscode_tasks_init(root, force, dry_run, &mut report)?;
+            } else {
+                warn!("VSCode tasks generation requires `Makefile`, skipping");
+            }
+        }
+    }
+
+    let has_compile_flags = has_makefile || find_compile_commands(root).is_some();
+    if clangd {
+        if has_compile_flags {
+            clangd_init(root, force, dry_run, &mut report)?;
+        } else {
+            warn!(".clangd generation requires `Makefile` or `compile_commands.json`, skipping");
+        }
+    }
+
+    if cpp_properties {
+        if has_compile_flags {
+            cpp_properties_init(root, force, dry_run, &mut report)?;
+        } else {
+            warn!(
+                "c_cpp_properties.json generation requires `Makefile` or `compile_commands.json`, skipping"
+            );
+        }
+    }
+
+    if doxygen {
+        doxygen_init(root, &config, force, dry_run, &mut report)?;
+    }
+
+    if !no_openocd {
+        match detect_stm32_family(root)
+            .as_deref()
+            .and_then(get_openocd_target)
+        {
+            Some(target) => {
+                info!("Generating openocd.cfg file...");
+                let path = root_path(root, "openocd.cfg");
+                let written = render_file(
+                    &path,
+                    OPENOCD_CFG,
+                    &OpenOCDContext {
+                        interface: probe.openocd_interface(),
+                        target,
+                    },
+                    force,
+                    dry_run,
+                )?;
+                report.file(&path, written);
+            }
+            None => warn!("Unable to detect STM32 family, skipping openocd.cfg"),
+        }
+    }
+
+    if !config.patches.is_empty() {
+        info!("Applying {} patches from config...", config.patches.len());
+        for patch in &config.patches {
+            let patch = in_dir(patch.clone(), root);
+            apply_and_report(&patch, dry_run, backup, confirm, &mut report)?;
+        }
+    }
+
+    if !dry_run {
+        record_generated(root, &report, &existing)?;
+    }
+
+    if use_git {
+        if dry_run {
+            info!("[dry-run] Would create initial commit");
+        } else {
+            git_initial_commit(root);
+        }
+    }
+
+    // 在初始提交之后安装，避免 hook 影响初始提交
+    if install_hooks {
+        let hooks_dir = root_path(root, ".git/hooks");
+        let changed = install_pre_commit_hook(&hooks_dir, dry_run)?;
+        report.patch(&format!("{hooks_dir}/pre-commit"), changed);
+    }
+
+    info!("STM32 project initialized!");
+    Ok(report)
+}
+
+/// 项目根目录下的路径，根目录为空时即为原路径
+fn root_path(root: &Path, path: &str) -> String {
+    root.join(path).to_string_lossy().to_string()
+}
+
+/// 项目根目录下已有的文件与目录名
+fn top_level_entries(root: &Path) -> HashSet<OsString> {
+    fs::read_dir(root.join("."))
+        .map(|entries| entries.flatten().map(|entry| entry.file_name()).collect())
+        .unwrap_or_default()
+}
+
+/// 将生成的文件与目录写入清单，供 clean 判断哪些内容由工具生成
+///
+/// 顶层目录在生成前不存在时，其中的各级目录（如 UserCode、.vscode）也一并记录
+fn record_generated(
+    root: &Path,
+    report: &InitReport,
+    existing: &HashSet<OsString>,
+) -> std::io::Result<()> {
+    let mut manifest = Manifest::load(root);
+    for path in &report.created {
+        let Ok(relative) = Path::new(path).strip_prefix(root) else {
+            continue;
+        };
+        let is_new = relative
+            .components()
+            .next()
+            .is_some_and(|top| !existing.contains(top.as_os_str()));
+        if is_new {
+            for dir in relative.ancestors().skip(1) {
+                if !dir.as_os_str().is_empty() {
+                    manifest.record(root, &dir.to_string_lossy().replace('\\', "/"));
+                }
+            }
+        }
+        manifest.record(root, &relative.to_string_lossy().replace('\\', "/"));
+    }
+    manifest.save(root)
+}
+
+/// Makefile 中的构建输出目录，没有 Makefile 或未设置时为 build
+fn makefile_build_dir(root: &Path) -> String {
+    fs::read_to_string(root.join("Makefile"))
+        .ok()
+        .and_then(|makefile| makefile_parser::parse_makefile(&makefile).build_dir)
+        .unwrap_or_else(|| "build".to_string())
+}
+
+/// 检测 STM32 系列，如 STM32F4
+///
+/// 优先读取 .ioc 中的 Mcu.Family，其次使用 Makefile 中的芯片宏定义
+fn detect_stm32_family(root: &Path) -> Option<String> {
+    if let Some(family) = get_ioc_value(root, "Mcu.Family") {
+        return Some(family);
+    }
+    let makefile = fs::read_to_string(root.join("Makefile")).ok()?;
+    let parsed_makefile = makefile_parser::parse_makefile(makefile.as_str());
+    let device = get_device_name(&parsed_makefile.defines)?;
+    device.get(..7).map(|family| family.to_string())
+}
+
+/// 将 STM32 系列映射为 OpenOCD 的 target 脚本名
+fn get_openocd_target(family: &str) -> Option<&'static str> {
+    let target = match family.get(5..)?.to_ascii_uppercase().as_str() {
+        "F0" => "stm32f0x",
+        "F1" => "stm32f1x",
+        "F2" => "stm32f2x",
+        "F3" => "stm32f3x",
+        "F4" => "stm32f4x",
+        "F7" => "stm32f7x",
+        "G0" => "stm32g0x",
+        "G4" => "stm32g4x",
+        "H7" => "stm32h7x",
+        "L0" => "stm32l0",
+        "L1" => "stm32l1",
+        "L4" => "stm32l4x",
+        "L5" => "stm32l5x",
+        "U5" => "stm32u5x",
+        "WB" => "stm32wbx",
+        "WL" => "stm32wlx",
+        _ => return None,
+    };
+    Some(target)
+}
+
+/// 初始化 git 仓库，指定 `branch` 时作为初始分支名
+fn git_init(root: &Path, branch: Option<&str>) {
+    info!("Initializing git repository...");
+    let status = match branch {
+        Some(branch) => match run_git(root, &["init", "-b", branch]) {
+            Ok(status) if !status.success() => {
+                // 旧版本 git 不支持 -b，先初始化再修改 HEAD
+                warn!("`git init -b` is not supported, setting initial branch via symbolic-ref");
+                run_git(root, &["init"]).and_then(|status| {
+                    let has_commits = run_git(root, &["rev-parse", "--verify", "HEAD"])?.success();
+                    if !status.success() || has_commits {
+                        return Ok(status);
+                    }
+                    run_git(
+                        root,
+                        &["symbolic-ref", "HEAD", &format!("refs/heads/{branch}")],
+                    )
+                })
+            }
+            status => status,
+        },
+        None => run_git(root, &["init"]),
+    };
+    match status {
+        Ok(status) if status.success() => {
+            info!("Git repository initialized successfully!");
+        }
+        Ok(status) => {
+            error!("Git init failed with status: {}", status);
+        }
+        Err(e) => {
+            error!("Failed to execute git: {}", e);
+        }
+    }
+}
+
+/// 在项目根目录执行 git 命令，屏蔽输出
+fn run_git(root: &Path, args: &[&str]) -> std::io::Result<std::process::ExitStatus> {
+    // -C 为空时 git 保持当前目录不变
+    Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(args)
+        .stdout(Stdio::null()) // 屏蔽 stdout
+        .stderr(Stdio::null()) // 屏蔽 stderr
+        .status()
+}
+
+/// 目录位于其他目录的 git 工作区中时返回该工作区的根目录，目录本身是仓库根目录时返回 None
+fn parent_work_tree(dir: &Path) -> Option<PathBuf> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--show-toplevel"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let toplevel = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let same = match (toplevel.canonicalize(), dir.canonicalize()) {
+        (Ok(toplevel), Ok(dir)) => toplevel == dir,
+        _ => false,
+    };
+    (!same).then_some(toplevel)
+}
+
+/// 目录是 git 仓库且有未提交的修改，包括未跟踪的文件
+fn has_uncommitted_changes(dir: &Path) -> bool {
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["status", "--porcelain", "--", "."])
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| output.status.success() && !output.stdout.is_empty())
+}
+
+/// 安装 pre-commit hook，已有 hook 时追加，通过标记注释避免重复安装
+fn install_pre_commit_hook(hooks_dir: &str, dry_run: bool) -> std::io::Result<bool> {
+    let path = format!("{hooks_dir}/pre-commit");
+    let marker = PRE_COMMIT_HOOK.lines().next().unwrap_or_default();
+
+    if !Path::new(hooks_dir).is_dir() {
+        warn!("`{}` not found, skipping pre-commit hook", hooks_dir);
+        return Ok(false);
+    }
+    let content = match fs::read_to_string(&path) {
+        Ok(content) if content.contains(marker) => {
+            info!("Pre-commit hook already installed");
+            return Ok(false);
+        }
+        Ok(content) => format!("{}\n{}", content.trim_end(), PRE_COMMIT_HOOK),
+        Err(_) => format!("#!/bin/sh\n{}", PRE_COMMIT_HOOK),
+    };
+
+    if dry_run {
+        info!("[dry-run] Would install pre-commit hook to {}", path);
+        return Ok(true);
+    }
+    fs::write(&path, content)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+    info!("Installed pre-commit hook to {}", path);
+    Ok(true)
+}
+
+/// 创建初始提交，仓库已有提交时跳过
+fn git_initial_commit(root: &Path) {
+    match run_git(root, &["rev-parse", "--verify", "HEAD"]) {
+        Ok(status) if status.success() => {
+            info!("Repository already has commits, skipping initial commit");
+            return;
+        }
+        Ok(_) => {}
+        Err(e) => {
+            warn!("Failed to execute git: {}", e);
+            return;
+        }
+    }
+
+    info!("Creating initial commit...");
+    let status = run_git(root, &["add", "-A"]).and_then(|status| {
+        if status.success() {
+            run_git(root, &["commit", "-m", "chore: initialize STM32 project"])
+        } else {
+            Ok(status)
+        }
+    });
+    match status {
+        Ok(status) if status.success() => {
+            info!("Initial commit created successfully!");
+        }
+        Ok(status) => {
+            warn!("Git commit failed with status: {}", status);
+        }
+        Err(e) => {
+            warn!("Failed to execute git: {}", e);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EIDEProjectFile<'a> {
+    path: &'a String,
+}
+
+fn eide_custom_init(
+    root: &Path,
+    config: &Config,
+    force: bool,
+    dry_run: bool,
+    probe: Probe,
+    flasher: Flasher,
+    report: &mut InitReport,
+) -> std::io::Result<()> {
+    let parsed_makefile = load_build_config(root)?;
+
+    let mut files =
+        Vec::with_capacity(parsed_makefile.asm_sources.len() + parsed_makefile.cpp_sources.len());
+    // 启动文件放在最前，其余汇编文件与 C++ 文件随后
+    let startup_file = parsed_makefile.startup_file.as_ref();
+    match startup_file {
+        Some(source) => files.push(EIDEProjectFile { path: source }),
+        None => warn!("No startup file found in ASM_SOURCES, the EIDE project may fail to link"),
+    }
+    for source in parsed_makefile
+        .asm_sources
+        .iter()
+        .filter(|source| Some(*source) != startup_file)
+        .chain(parsed_makefile.cpp_sources.iter())
+    {
+        files.push(EIDEProjectFile { path: source });
+    }
+
+    // 链接脚本缺失时 EIDE 无法链接，仅提示，不影响生成
+    if let Some(ldscript) = &parsed_makefile.ldscript
+        && parsed_makefile.resolve_ldscript(root).is_none()
+    {
+        warn!(
+            "Linker script {} not found, the EIDE project may fail to link",
+            root.join(ldscript).display()
+        );
+    }
+
+    // EIDE 需要工具链的安装目录，GCC_PATH 通常是其中的 bin 目录
+    let gcc_install_dir = parsed_makefile
+        .gcc_path
+        .as_deref()
+        .map(|path| {
+            let path = path.trim_end_matches('/');
+            serde_json::to_string(path.strip_suffix("/bin").unwrap_or(path))
+        })
+        .transpose()?;
+    let gcc_prefix = serde_json::to_string(parsed_makefile.tool_prefix())?;
+
+    // OpenOCD 烧录需要芯片系列对应的 target 脚本，无法识别时退回 ST-Link
+    let openocd_target = match flasher {
+        Flasher::Openocd => {
+            let target = detect_stm32_family(root)
+                .as_deref()
+                .and_then(get_openocd_target);
+            if target.is_none() {
+                warn!("Unable to detect OpenOCD target, falling back to ST-Link uploader");
+            }
+            target
+        }
+        Flasher::Stlink => None,
+    };
+    let chip_name = get_chip_name(root, &parsed_makefile.defines);
+    let device_name = serde_json::to_string(&chip_name)?;
+    let chip_name = chip_name.unwrap_or_default();
+
+    let project_name = parsed_makefile.target.unwrap_or("".to_string());
+
+    let src = config.find_source_dirs(root);
+    // EIDE 递归编译源码目录，同名源文件会生成同名目标文件，或因重复定义导致链接失败
+    for (name, paths) in find_duplicate_sources(root, &src, &parsed_makefile.c_sources) {
+        warn!(
+            "Duplicate source file {} found in: {}",
+            name,
+            paths.join(", ")
+        );
+    }
+    // EIDE 的 C 与汇编共用头文件路径，仅汇编使用的宏通过 ASM_FLAGS 传入
+    let mut includes = parsed_makefile.includes;
+    for include in parsed_makefile.as_includes {
+        if !includes.contains(&include) {
+            includes.push(include);
+        }
+    }
+    includes.push("UserCode".to_string());
+    let mut asm_flags: Vec<String> = parsed_makefile
+        .as_defines
+        .iter()
+        .filter(|define| !parsed_makefile.defines.contains(define))
+        .map(|define| format!("-D{define}"))
+        .collect();
+    // EIDE 按 .s 汇编，.S 需要先经过预处理，否则其中的 #include、#define 会报错
+    if let Some(source) = parsed_makefile
+        .asm_sources
+        .iter()
+        .find(|source| get_asm_kind(source) == AsmKind::Preprocessed)
+    {
+        info!(
+            "Found preprocessed assembly {}, enabling the C preprocessor for assembly",
+            source
+        );
+        asm_flags.insert(0, "-x assembler-with-cpp".to_string());
+    }
+    let asm_flags = asm_flags.join(" ");
+
+    // Debug 与 Makefile 一致，Release 去掉 DEBUG 宏、加上 NDEBUG 并提高优化等级
+    let debug_optimization = get_eide_optimization(parsed_makefile.opt.as_deref());
+    let release_optimization = match debug_optimization {
+        "level-debug" | "level-0" | "level-1" => "level-2",
+        optimization => optimization,
+    };
+    let release_defines: Vec<&String> = parsed_makefile
+        .defines
+        .iter()
+        .filter(|define| define.split('=').next() != Some("DEBUG"))
+        .collect();
+    let mut release_define_list = serde_json::to_value(&release_defines)?;
+    if let Some(list) = release_define_list.as_array_mut()
+        && !release_defines.iter().any(|d| *d == "NDEBUG")
+    {
+        list.push("NDEBUG".into());
+    }
+    let ld_file_path = parsed_makefile.ldscript.clone().unwrap_or_default();
+    let include_list = serde_json::to_string(&includes)?;
+    let asm_flags = serde_json::to_string(&asm_flags)?;
+    let cpu_type = get_eide_cpu_type(parsed_makefile.cpu.as_deref());
+    let floating_point_hardware = get_eide_floating_point_hardware(
+        parsed_makefile.fpu.as_deref(),
+        parsed_makefile.float_abi.as_deref(),
+    );
+    let float_abi_type = parsed_makefile.float_abi.as_deref().unwrap_or("hard");
+    let target = |name, id, optimization, debug_info, define_list| EIDETargetContext {
+        name,
+        id,
+        optimization,
+        debug_info,
+        define_list,
+        asm_flags: asm_flags.clone(),
+        ld_file_path: &ld_file_path,
+        include_list: &include_list,
+        cpu_type: &cpu_type,
+        floating_point_hardware,
+        float_abi_type,
+    };
+
+    let ctx = EIDEConfigContext {
+        project_name: &project_name,
+        src_dirs: &serde_json::to_string(&src)?,
+        src_files: &serde_json::to_string(&files)?,
+        targets: vec![
+            target(
+                "Debug",
+                "debug",
+                debug_optimization,
+                true,
+                serde_json::to_string(&parsed_makefile.defines)?,
+            ),
+            target(
+                "Release",
+                "release",
+                release_optimization,
+                false,
+                release_define_list.to_string(),
+            ),
+        ],
+        gcc_prefix,
+        gcc_install_dir,
+        uploader: if openocd_target.is_some() {
+            "OpenOCD"
+        } else {
+            "STLink"
+        },
+        openocd_target,
+        openocd_interface: probe.openocd_interface(),
+        chip_name,
+        device_name,
+    };
+
+    info!("Generating EIDE config file...");
+    let path = root_path(root, ".eide/eide.json");
+    // 模板中拼接了预先序列化的 JSON，转义出错时 EIDE 会静默忽略配置，写入前先检查
+    let content = render_string(&path, EIDE_CONFIG, &ctx)?;
+    if let Err(e) = serde_json::from_str::<serde_json::Value>(&content) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Generated {path} is not valid JSON: {e}"),
+        ));
+    }
+    let written = write_file(&path, &content, force, dry_run)?;
+    report.file(&path, written);
+    info!("Generating EIDE workspace file...");
+    let workspace = root_path(root, &format!("{project_name}.code-workspace"));
+    let written = render_file(&workspace, EIDE_WORKSPACE, &ctx, force, dry_run)?;
+    report.file(&workspace, written);
+
+    let launch_ctx = LaunchContext {
+        executable: &format!(
+            "{}/{}.elf",
+            parsed_makefile.build_dir.as_deref().unwrap_or("build"),
+            project_name
+        ),
+        device: &get_device_name(&parsed_makefile.defines).unwrap_or_default(),
+        probe: probe.display_name(),
+        servertype: probe.servertype(),
+        openocd: probe.servertype() == "openocd",
+    };
+    info!("Generating VSCode launch file...");
+    let path = root_path(root, ".vscode/launch.json");
+    let written = render_file(&path, VSCODE_LAUNCH, &launch_ctx, force, dry_run)?;
+    report.file(&path, written);
+
+    Ok(())
+}
+
+/// 生成 VSCode 的编译与烧录任务
+fn vscode_tasks_init(
+    root: &Path,
+    force: bool,
+    dry_run: bool,
+    report: &mut InitReport,
+) -> std::io::Result<()> {
+    let makefile = fs::read_to_string(root.join("Makefile"))?;
+    let parsed_makefile = makefile_parser::parse_makefile(makefile.as_str());
+
+    let output = format!(
+        "{}/{}",
+        parsed_makefile.build_dir.as_deref().unwrap_or("build"),
+        parsed_makefile.target.as_deref().unwrap_or_default()
+    );
+    let ctx = TasksContext {
+        elf: &format!("{output}.elf"),
+        bin: &format!("{output}.bin"),
+    };
+
+    info!("Generating VSCode tasks file...");
+    let path = root_path(root, ".vscode/tasks.json");
+    let written = render_file(&path, VSCODE_TASKS, &ctx, force, dry_run)?;
+    report.file(&path, written);
+    Ok(())
+}
+
+/// 解析项目的 Makefile，存在 compile_commands.json 时以其中的头文件路径和宏定义为准
+///
+/// 没有 Makefile 时仅使用 compile_commands.json 中的信息
+fn load_build_config(root: &Path) -> std::io::Result<MakefileConfig> {
+    let compile_commands = find_compile_commands(root);
+    let makefile = match fs::read_to_string(root.join("Makefile")) {
+        Ok(makefile) => makefile,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && compile_commands.is_some() => {
+            String::new()
+        }
+        Err(e) => return Err(e),
+    };
+    let mut parsed_makefile = makefile_parser::parse_makefile(&makefile);
+    if let Some(path) = compile_commands
+        && let Some(flags) = load_compile_flags(root, &path)
+    {
+        info!("Using include paths and defines from {}", path.display());
+        parsed_makefile.includes = flags.includes;
+        parsed_makefile.set_defines(flags.defines);
+    }
+    Ok(parsed_makefile)
+}
+
+/// 根据 Makefile 中的头文件路径和宏定义生成 .clangd
+fn clangd_init(
+    root: &Path,
+    force: bool,
+    dry_run: bool,
+    report: &mut InitReport,
+) -> std::io::Result<()> {
+    let parsed_makefile = load_build_config(root)?;
+
+    let flags = parsed_makefile
+        .includes
+        .iter()
+        .map(String::as_str)
+        .chain(["UserCode"])
+        .map(|include| format!("-I{include}"))
+        .chain(parsed_makefile.define_flags())
+        .map(|flag| serde_json::to_string(&flag))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    info!("Generating .clangd file...");
+    let path = root_path(root, ".clangd");
+    let ctx = ClangdContext {
+        compiler: serde_json::to_string(&parsed_makefile.c_compiler())?,
+        flags,
+    };
+    let written = render_file(&path, CLANGD, &ctx, force, dry_run)?;
+    report.file(&path, written);
+    Ok(())
+}
+
+/// 根据 Makefile 中的头文件路径和宏定义生成 C/C++ 扩展使用的 c_cpp_properties.json
+fn cpp_properties_init(
+    root: &Path,
+    force: bool,
+    dry_run: bool,
+    report: &mut InitReport,
+) -> std::io::Result<()> {
+    let parsed_makefile = load_build_config(root)?;
+
+    let include_path = parsed_makefile
+        .includes
+        .iter()
+        .map(String::as_str)
+        .chain(["UserCode"])
+        .map(|include| {
+            if Path::new(include).is_absolute() {
+                include.to_string()
+            } else {
+                format!("${{workspaceFolder}}/{include}")
+            }
+        })
+        .collect::<Vec<_>>();
+
+    info!("Generating .vscode/c_cpp_properties.json...");
+    let path = root_path(root, ".vscode/c_cpp_properties.json");
+    let ctx = CppPropertiesContext {
+        include_path: serde_json::to_string(&include_path)?,
+        defines: serde_json::to_string(&parsed_makefile.defines)?,
+        compiler_path: serde_json::to_string(&parsed_makefile.c_compiler())?,
+    };
+    let written = render_file(&path, C_CPP_PROPERTIES, &ctx, force, dry_run)?;
+    report.file(&path, written);
+    Ok(())
+}
+
+/// 项目名，依次取 Makefile 的 TARGET、.ioc 中的项目名和项目目录名
+fn detect_project_name(root: &Path) -> String {
+    fs::read_to_string(root.join("Makefile"))
+        .ok()
+        .and_then(|makefile| makefile_parser::parse_makefile(&makefile).target)
+        .or_else(|| get_ioc_value(root, "ProjectManager.ProjectName"))
+        .or_else(|| {
+            let dir = if root.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                root
+            };
+            let dir = dir.canonicalize().ok()?;
+            Some(dir.file_name()?.to_string_lossy().to_string())
+        })
+        .unwrap_or_default()
+}
+
+/// 生成扫描用户代码与 Core 的 Doxyfile，并创建 docs 输出目录
+fn doxygen_init(
+    root: &Path,
+    config: &Config,
+    force: bool,
+    dry_run: bool,
+    report: &mut InitReport,
+) -> std::io::Result<()> {
+    // 用户代码目录取配置中各目录的顶层目录，如 UserCode
+    let mut input = config.user_code_dirs();
+    if !input.contains(&"Core") {
+        input.push("Core");
+    }
+
+    info!("Generating Doxyfile...");
+    let path = root_path(root, "Doxyfile");
+    let ctx = DoxygenContext {
+        project_name: &detect_project_name(root),
+        input: &input.join(" "),
+        mainpage: &config.readme_path(),
+    };
+    let written = render_file(&path, DOXYFILE, &ctx, force, dry_run)?;
+    report.file(&path, written);
+
+    let docs = root_path(root, "docs");
+    if !Path::new(&docs).is_dir() {
+        report.created.push(docs.clone());
+        if dry_run {
+            info!("[dry-run] Would create dir {}", docs);
+        } else {
+            fs::create_dir_all(&docs)?;
+            info!("Created dir {}", docs);
+        }
+    }
+    Ok(())
+}
+
+/// 从宏定义中获取芯片型号，如 STM32F407xx -> STM32F407
+fn get_device_name(defines: &[String]) -> Option<String> {
+    defines
+        .iter()
+        .find(|d| d.starts_with("STM32") && !d.contains(['_', '=']))
+        .map(|d| d.trim_end_matches('x').to_string())
+}
+
+/// 递归收集目录下的 C/C++ 与汇编源文件，忽略无法读取的目录
+fn collect_source_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_source_files(&path, files);
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| matches!(ext, "c" | "cpp" | "cc" | "cxx" | "s" | "S" | "sx"))
+        {
+            files.push(path);
+        }
+    }
+}
+
+/// 查找源码目录与 Makefile C_SOURCES 中文件名相同的源文件，返回文件名与各自的路径
+fn find_duplicate_sources(
+    root: &Path,
+    dirs: &[String],
+    c_sources: &[String],
+) -> Vec<(String, Vec<String>)> {
+    let mut files = Vec::new();
+    for dir in dirs {
+        collect_source_files(&root.join(dir), &mut files);
+    }
+    let files = files
+        .iter()
+        .map(|path| path.strip_prefix(root).unwrap_or(path).to_path_buf())
+        .chain(c_sources.iter().map(PathBuf::from));
+
+    // 同一文件可能既在源码目录中又在 C_SOURCES 中，按规范化的路径去重
+    let mut by_name: Vec<(String, Vec<String>)> = Vec::new();
+    for path in files {
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let path = path.to_string_lossy().replace('\\', "/");
+        let path = path.trim_start_matches("./").to_string();
+        match by_name.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, paths)) if paths.contains(&path) => {}
+            Some((_, paths)) => paths.push(path),
+            None => by_name.push((name, vec![path])),
+        }
+    }
+    by_name.retain(|(_, paths)| paths.len() > 1);
+    by_name
+}
+
+/// 芯片名，如 STM32F407VE，用于 EIDE 的烧录配置
+///
+/// 优先取 .ioc 中 Mcu.CPN 去掉封装与温度后缀的部分，其次使用 Makefile 中的芯片宏定义
+fn get_chip_name(root: &Path, defines: &[String]) -> Option<String> {
+    if let Some(cpn) = get_ioc_value(root, "Mcu.CPN") {
+        return Some(cpn.get(..11).unwrap_or(&cpn).to_string());
+    }
+    get_device_name(defines)
+}
+
+/// 汇编源文件的类型
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum AsmKind {
+    /// .s，直接汇编
+    Plain,
+    /// .S、.sx，汇编前需要 C 预处理
+    Preprocessed,
+}
+
+/// 按扩展名区分汇编源文件，GCC 的约定是大写 .S 需要预处理
+fn get_asm_kind(path: &str) -> AsmKind {
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("S") | Some("sx") => AsmKind::Preprocessed,
+        _ => AsmKind::Plain,
+    }
+}
+
+/// 将 Makefile 中的 OPT 转换为 EIDE 的优化等级
+fn get_eide_optimization(opt: Option<&str>) -> &'static str {
+    match opt.map(str::trim) {
+        Some("-O0") => "level-0",
+        Some("-O1") | Some("-O") => "level-1",
+        Some("-O2") => "level-2",
+        Some("-O3") => "level-3",
+        Some("-Os") | Some("-Oz") => "level-size",
+        Some("-Ofast") => "level-fast",
+        _ => "level-debug",
+    }
+}
+
+/// 默认目录在 README 中的说明
+const DIRECTORY_DESCRIPTIONS: [(&str, &str); 7] = [
+    ("bsp", "板级驱动，控制板上外设 (I2C, SPI, UART, CAN, ...)"),
+    ("drivers", "驱动程序，驱动硬件"),
+    ("third_party", "第三方（比如厂家）提供的驱动库"),
+    (
+        "libs",
+        "库文件，提供算法、功能逻辑、通用模块，不依赖具体硬件",
+    ),
+    ("interfaces", "接口层，用于对不同的外设提供统一的向上接口"),
+    ("controllers", "控制层，用于实现 外设+硬件"),
+    ("app", "应用层"),
+];
+
+/// 选择要生成的 UserCode 目录，按配置中的顺序返回，app 目录始终保留
+///
+/// 指定 `dirs` 时按目录名或完整路径匹配，否则交互时多选，默认全部选中
+fn select_directories(
+    config: &Config,
+    dirs: Option<&[String]>,
+    interactive: bool,
+) -> anyhow::Result<Vec<String>> {
+    let app_dir = config.app_dir();
+    let optional: Vec<&String> = config
+        .directories
+        .iter()
+        .filter(|d| d.trim_end_matches('/') != app_dir)
+        .collect();
+
+    let selected: Vec<&String> = match dirs {
+        Some(dirs) => {
+            let mut selected = Vec::new();
+            for name in dirs {
+                let name = name.trim().trim_end_matches('/');
+                if name.is_empty() {
+                    continue;
+                }
+                let dir = config.directories.iter().find(|d| {
+                    let d = d.trim_end_matches('/');
+                    d == name || Path::new(d).file_name().is_some_and(|n| n == name)
+                });
+                match dir {
+                    Some(dir) => selected.push(dir),
+                    None => {
+                        let known: Vec<&str> =
+                            config.directories.iter().map(String::as_str).collect();
+                        return Err(anyhow!(
+                            "Unknown directory `{}`, available: {}",
+                            name,
+                            known.join(", ")
+                        ));
+                    }
+                }
+            }
+            selected
+        }
+        None if interactive && !optional.is_empty() => MultiSelect::new()
+            .with_prompt(format!(
+                "Choose user code directories ({app_dir} is always created)"
+            ))
+            .items(&optional)
+            .defaults(&vec![true; optional.len()])
+            .interact()?
+            .into_iter()
+            .map(|i| optional[i])
+            .collect(),
+        None => optional.clone(),
+    };
+
+    Ok(config
+        .directories
+        .iter()
+        .filter(|d| d.trim_end_matches('/') == app_dir || selected.contains(d))
+        .cloned()
+        .collect())
+}
+
+/// 按生成的目录生成 README 中的目录树，路径相对于 README 所在目录
+fn directory_tree(directories: &[String], app_dir: &str, readme: &str) -> String {
+    let root = Path::new(readme)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut dirs: Vec<&str> = directories
+        .iter()
+        .map(|d| d.trim_end_matches('/'))
+        .collect();
+    if !dirs.contains(&app_dir) {
+        dirs.push(app_dir);
+    }
+
+    let mut tree = format!("{}/\n", root.to_string_lossy());
+    for (i, dir) in dirs.iter().enumerate() {
+        let relative = Path::new(dir).strip_prefix(root).unwrap_or(Path::new(dir));
+        let branch = if i + 1 == dirs.len() {
+            "└──"
+        } else {
+            "├──"
+        };
+        let entry = format!("{} {}/", branch, relative.to_string_lossy());
+        let description = Path::new(dir).file_name().and_then(|name| {
+            DIRECTORY_DESCRIPTIONS
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, d)| d)
+        });
+        match description {
+            Some(description) => tree.push_str(&format!("{:<30}# {}\n", entry, description)),
+            None => tree.push_str(&format!("{}\n", entry)),
+        }
+    }
+    tree.trim_end().to_string()
+}
+
+/// 时钟、中断等由 CubeMX 管理的外设，不生成驱动桩
+const SYSTEM_IPS: [&str; 3] = ["NVIC", "RCC", "SYS"];
+
+/// 为 .ioc 中启用的外设生成驱动桩，同类外设（如 USART1、USART2）共用一个文件。
+/// 文件名带 drv_ 前缀，避免与 CubeMX 生成的 usart.c/usart.h 同名
+fn scaffold_driver_stubs(
+    root: &Path,
+    drivers_dir: &str,
+    ctx: &InitContext,
+    force: bool,
+    dry_run: bool,
+    report: &mut InitReport,
+) -> io::Result<()> {
+    let mut peripherals: Vec<(String, Vec<String>)> = Vec::new();
+    for ip in get_ioc_peripherals(root) {
+        if SYSTEM_IPS.contains(&ip.as_str()) {
+            continue;
+        }
+        let name = ip
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .to_ascii_lowercase();
+        match peripherals.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, instances)) => instances.push(ip),
+            None => peripherals.push((name, vec![ip])),
+        }
+    }
+    if peripherals.is_empty() {
+        warn!("No peripherals found in .ioc, skipping driver stubs");
+        return Ok(());
+    }
+
+    for (name, instances) in &peripherals {
+        let driver_ctx = DriverContext {
+            author: &ctx.author,
+            email: &ctx.email,
+            date: &ctx.date,
+            name,
+            guard: &name.to_ascii_uppercase(),
+            instances: &instances.join(", "),
+        };
+        for (path, template) in [
+            (format!("{drivers_dir}/drv_{name}.h"), DRIVER_H),
+            (format!("{drivers_dir}/drv_{name}.c"), DRIVER_C),
+        ] {
+            let written = render_file(&path, template, &driver_ctx, force, dry_run)?;
+            report.file(&path, written);
+        }
+    }
+    Ok(())
+}
+
+/// STM32CubeMX 生成的 CMake 工程文件
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CMakeLists {
+    /// CLion 使用的模板，CMakeLists.txt 由 STM32CubeMX 根据它生成
+    Template,
+    /// STM32CubeMX 6.11 起直接生成的 CMakeLists.txt，只生成一次，之后由用户维护
+    Plain,
+}
+
+impl CMakeLists {
+    /// 查找项目中的 CMake 工程文件，两者都存在时优先使用模板
+    fn detect(root: &Path) -> Option<CMakeLists> {
+        [CMakeLists::Template, CMakeLists::Plain]
+            .into_iter()
+            .find(|c| root.join(c.file_name()).is_file())
+    }
+
+    fn file_name(self) -> &'static str {
+        match self {
+            CMakeLists::Template => "CMakeLists_template.txt",
+            CMakeLists::Plain => "CMakeLists.txt",
+        }
+    }
+}
+
+/// 非侵入式引入头文件的补丁，`cmake_lists` 为项目使用的 CMake 工程文件
+fn non_intrusive_header_patches(cmake_lists: Option<CMakeLists>, app_dir: &str) -> Vec<Patch> {
+    let app_h = format!("{app_dir}/app.h");
+    let mut patches = Vec::new();
+    match cmake_lists {
+        Some(CMakeLists::Template) => patches.push(Patch::Append {
+            file: CMakeLists::Template.file_name().to_string(),
+            after: "add_executable".to_string(),
+            insert: format!("\n# 非侵入式引入头文件\ntarget_compile_options(${{PROJECT_NAME}}.elf PRIVATE -include ${{CMAKE_SOURCE_DIR}}/{app_h})\n"),
+            marker: app_h.clone(),
+        }),
+        Some(CMakeLists::Plain) => patches.push(Patch::Append {
+            file: CMakeLists::Plain.file_name().to_string(),
+            after: "add_executable(${CMAKE_PROJECT_NAME})".to_string(),
+            insert: format!("\n# 非侵入式引入头文件\ntarget_compile_options(${{CMAKE_PROJECT_NAME}} PRIVATE -include ${{CMAKE_SOURCE_DIR}}/{app_h})\n"),
+            marker: app_h.clone(),
+        }),
+        None => {}
+    }
+    patches.push(Patch::Append {
+        file: "Makefile".to_string(),
+        after: "CFLAGS += $(MCU)".to_string(),
+        insert: format!("\n# 非侵入式引入头文件\nCFLAGS += -include {app_h}\n"),
+        marker: app_h,
+    });
+    patches
+}
+
+/// STM32CubeMX 生成的 CMake 工程中的工具链文件
+const CMAKE_TOOLCHAIN_FILE: &str = "cmake/gcc-arm-none-eabi.cmake";
+
+/// 在工具链文件设置 C++ 编译参数之后追加 UserCode 头文件路径与额外参数
+fn cmake_toolchain_patch(extra_flags: Option<&str>) -> Patch {
+    let mut flags = "-I${CMAKE_SOURCE_DIR}/UserCode".to_string();
+    if let Some(extra_flags) = extra_flags {
+        flags.push(' ');
+        flags.push_str(&cmake_escape(extra_flags.trim()));
+    }
+    Patch::Append {
+        file: CMAKE_TOOLCHAIN_FILE.to_string(),
+        after: "set(CMAKE_CXX_FLAGS".to_string(),
+        insert: format!(
+            "\n# stm32-tool: UserCode flags\nset(STM32_TOOL_FLAGS \"{flags}\")\nset(CMAKE_C_FLAGS \"${{CMAKE_C_FLAGS}} ${{STM32_TOOL_FLAGS}}\")\nset(CMAKE_CXX_FLAGS \"${{CMAKE_CXX_FLAGS}} ${{STM32_TOOL_FLAGS}}\")\n"
+        ),
+        marker: "# stm32-tool: UserCode flags".to_string(),
+    }
+}
+
+/// 读取工具链文件中已追加的额外参数，用于还原补丁
+fn installed_toolchain_flags(root: &Path) -> Option<String> {
+    let content = fs::read_to_string(root.join(CMAKE_TOOLCHAIN_FILE)).ok()?;
+    let re = Regex::new(
+        r#"set\(STM32_TOOL_FLAGS "-I\$\{CMAKE_SOURCE_DIR\}/UserCode ((?:[^"\\]|\\.)+)"\)"#,
+    )
+    .unwrap();
+    re.captures(&content).map(|caps| cmake_unescape(&caps[1]))
+}
+
+/// 转义 CMake 带引号参数中的 `\` 与 `"`，保留 `${...}` 变量引用
+fn cmake_escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// 还原 `cmake_escape` 转义的内容
+fn cmake_unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// 向 STM32CubeMX 直接生成的 CMakeLists.txt 添加 UserCode 源文件与头文件路径，
+/// 插入到其预留的用户代码注释之后
+fn cmake_lists_patches() -> Vec<Patch> {
+    let file = CMakeLists::Plain.file_name();
+    vec![
+        Patch::Append {
+            file: file.to_string(),
+            after: "add_executable(${CMAKE_PROJECT_NAME})".to_string(),
+            insert: "\n# UserCode 源文件\nfile(GLOB_RECURSE USER_SOURCES CONFIGURE_DEPENDS \"${CMAKE_SOURCE_DIR}/UserCode/*.c\" \"${CMAKE_SOURCE_DIR}/UserCode/*.cpp\")\n".to_string(),
+            marker: "file(GLOB_RECURSE USER_SOURCES".to_string(),
+        },
+        Patch::Replace {
+            file: file.to_string(),
+            find: "# Add user sources here".to_string(),
+            insert: "# Add user sources here\n    ${USER_SOURCES}".to_string(),
+        },
+        Patch::Replace {
+            file: file.to_string(),
+            find: "# Add user defined include paths".to_string(),
+            insert: "# Add user defined include paths\n    ${CMAKE_SOURCE_DIR}/UserCode".to_string(),
+        },
+    ]
+}
+
+/// CLion 环境初始化的补丁
+fn clion_patches() -> Vec<Patch> {
+    vec![
+        Patch::Replace {
+            file: "CMakeLists_template.txt".to_string(),
+            find: "include_directories(${includes})".to_string(),
+            insert: "include_directories(${includes} UserCode)".to_string(),
+        },
+        Patch::Replace {
+            file: "CMakeLists_template.txt".to_string(),
+            find: "file(GLOB_RECURSE SOURCES ${sources})".to_string(),
+            insert: "file(GLOB_RECURSE SOURCES ${sources} \"UserCode/*.*\")".to_string(),
+        },
+    ]
+}
+
+/// CMakeLists_template.txt 中的硬件、软件浮点配置，依次返回
+fn fpu_patches() -> [Patch; 2] {
+    ["hardware", "software"].map(|kind| Patch::Uncomment {
+        file: "CMakeLists_template.txt".to_string(),
+        header: format!("#Uncomment for {kind} floating point"),
+    })
+}
+
+/// 将 -mcpu 转换为 EIDE 的 CPU 类型，如 cortex-m4 -> Cortex-M4
+fn get_eide_cpu_type(cpu: Option<&str>) -> String {
+    let Some(cpu) = cpu else {
+        return "Cortex-M4".to_string();
+    };
+    cpu.split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("-")
+        .replace("plus", "+")
+}
+
+/// 根据 -mfpu 与 -mfloat-abi 确定 EIDE 的硬件浮点类型
+fn get_eide_floating_point_hardware(fpu: Option<&str>, float_abi: Option<&str>) -> &'static str {
+    match (fpu, float_abi) {
+        (_, Some("soft")) => "none",
+        (Some(fpu), _) if !fpu.contains("sp") => "double",
+        _ => "single",
+    }
+}
+
+/// 芯片没有 FPU 时，硬件浮点会导致链接失败，回退为软件浮点
+fn check_fpu_support(root: &Path, fpu: FPUType) -> FPUType {
+    let Some(part_number) = get_ioc_part_number(root).or_else(|| detect_stm32_family(root)) else {
+        return fpu;
+    };
+    if fpu == FPUType::Hard && has_fpu(&part_number) == Some(false) {
+        warn!(
+            "{} has no FPU, falling back to software floating point",
+            part_number
+        );
+        return FPUType::Soft;
+    }
+    fpu
+}
+
+/// 检查 FPU 类型是否与 Makefile 中声明的浮点 ABI 一致
+fn check_fpu_type(root: &Path, fpu: FPUType) -> std::io::Result<()> {
+    let makefile = fs::read_to_string(root.join("Makefile"))?;
+    let parsed_makefile = makefile_parser::parse_makefile(makefile.as_str());
+    let declared = match parsed_makefile.float_abi.as_deref() {
+        Some("hard") => FPUType::Hard,
+        Some("soft") => FPUType::Soft,
+        _ => return Ok(()),
+    };
+    if declared != fpu {
+        warn!(
+            "FPU type {:?} does not match `-mfloat-abi={}` declared in Makefile",
+            fpu,
+            parsed_makefile.float_abi.unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// 向 CMakePresets.json 注入 UserCode 头文件路径以及 Debug/Release 配置
+///
+/// 只做增量合并，保留已有的 preset
+fn cmake_presets_init(path: &str, dry_run: bool) -> std::io::Result<bool> {
+    const INCLUDE_VARIABLES: [&str; 2] = [
+        "CMAKE_C_STANDARD_INCLUDE_DIRECTORIES",
+        "CMAKE_CXX_STANDARD_INCLUDE_DIRECTORIES",
+    ];
+
+    let content = fs::read_to_string(path)?;
+    let mut presets: serde_json::Value = match serde_json::from_str(&content) {
+        Ok(presets) => presets,
+        Err(e) => {
+            warn!("Invalid {}: {}, skipping", path, e);
+            return Ok(false);
+        }
+    };
+    let Some(root) = presets.as_object_mut() else {
+        warn!("Invalid {}: root is not an object, skipping", path);
+        return Ok(false);
+    };
+    let Some(configure_presets) = root
+        .entry("configurePresets")
+        .or_insert_with(|| serde_json::json!([]))
+        .as_array_mut()
+    else {
+        warn!(
+            "Invalid {}: `configurePresets` is not an array, skipping",
+            path
+        );
+        return Ok(false);
+    };
+
+    // 优先继承已有的隐藏 preset
+    let base = configure_presets
+        .iter()
+        .find(|p| p["hidden"].as_bool() == Some(true))
+        .and_then(|p| p["name"].as_str())
+        .map(|name| name.to_string());
+    for build_type in ["Debug", "Release"] {
+        if configure_presets.iter().any(|p| p["name"] == build_type) {
+            continue;
+        }
+        let mut preset = serde_json::json!({
+            "name": build_type,
+            "displayName": build_type,
+            "cacheVariables": { "CMAKE_BUILD_TYPE": build_type },
+        });
+        match &base {
+            Some(base) => preset["inherits"] = serde_json::json!(base),
+            None => preset["binaryDir"] = serde_json::json!("${sourceDir}/build/${presetName}"),
+        }
+        configure_presets.push(preset);
+        info!("Added configure preset {}", build_type);
+    }
+
+    for preset in configure_presets.iter_mut() {
+        let Some(preset) = preset.as_object_mut() else {
+            continue;
+        };
+        let Some(cache_variables) = preset
+            .entry("cacheVariables")
+            .or_insert_with(|| serde_json::json!({}))
+            .as_object_mut()
+        else {
+            continue;
+        };
+        for variable in INCLUDE_VARIABLES {
+            cache_variables
+                .entry(variable)
+                .or_insert_with(|| serde_json::json!("${sourceDir}/UserCode"));
+        }
+    }
+
+    // 与 STM32CubeMX 生成的文件保持一致，使用 4 空格缩进
+    let mut buf = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"    ");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut buf, formatter);
+    presets.serialize(&mut serializer)?;
+    let new_content = String::from_utf8_lossy(&buf).to_string() + "\n";
+    if new_content == content {
+        return Ok(false);
+    }
+    if dry_run {
+        log_dry_run_diff(path, &content, &new_content);
+        return Ok(true);
+    }
+    fs::write(path, new_content)?;
+    Ok(true)
+}
+
+/// 应用补丁并记录结果，找不到插入位置时给出警告，返回文件是否被修改
+fn apply_and_report(
+    patch: &Patch,
+    dry_run: bool,
+    backup: bool,
+    confirm: bool,
+    report: &mut InitReport,
+) -> std::io::Result<bool> {
+    let status = apply_patch(patch, dry_run, backup, confirm)?;
+    if status == PatchStatus::AnchorNotFound {
+        warn!(
+            "Patch anchor not found in {}, the template may have changed, skipping",
+            get_file(patch)
+        );
+    }
+    report.patch(get_file(patch), status.changed());
+    Ok(status.changed())
+}
+
+fn clion_custom_init(
+    root: &Path,
+    fpu: FPUType,
+    dry_run: bool,
+    backup: bool,
+    confirm: bool,
+    report: &mut InitReport,
+) -> std::io::Result<()> {
+    for patch in clion_patches() {
+        let patch = in_dir(patch, root);
+        apply_and_report(&patch, dry_run, backup, confirm, report)?;
+    }
+    let [hardware_fpu, software_fpu] = fpu_patches().map(|patch| in_dir(patch, root));
+    // 先还原另一种浮点配置，避免两者同时生效
+    let (enabled, disabled) = match fpu {
+        FPUType::Hard => (&hardware_fpu, &software_fpu),
+        FPUType::Soft => (&software_fpu, &hardware_fpu),
+    };
+    let reverted = revert_patch(disabled, dry_run, backup)?;
+    let applied = apply_and_report(enabled, dry_run, backup, confirm, report)?;
+    report.patch(get_file(enabled), reverted || applied);
+    Ok(())
+}
+
+/// 修改 CMakeLists_template.txt 后使用 STM32CubeMX 重新生成代码，失败时仅提示
+fn regenerate_code(
+    root: &Path,
+    dry_run: bool,
+    cubemx_timeout: Duration,
+    cubemx_retries: u32,
+    couple_files: bool,
+) {
+    if dry_run {
+        info!("[dry-run] Would regenerate code using STM32CubeMX");
+        return;
+    }
+    info!("Try to regenerate code(using STM32CubeMX)...");
+    let toolchain = Toolchain::from_ioc(root).unwrap_or(Toolchain::STM32CubeIDE);
+    match generate_code(
+        root,
+        Some(toolchain),
+        couple_files,
+        cubemx_timeout,
+        cubemx_retries,
+    ) {
+        Ok(_) => {
+            info!("Regenerate code successfully!")
+        }
+        Err(e) => {
+            warn!(
+                "Regenerate code failed ({}), please regenerate code manually!",
+                e
+            );
+        }
+    };
+}
+
+/// 修改 .ioc 中的配置项，不存在时追加到文件末尾
+fn set_ioc_value(ioc_file: &Path, key: &str, value: &str, backup: bool) -> std::io::Result<()> {
+    let file = ioc_file.to_string_lossy().to_string();
+    let patch = Patch::RegexReplace {
+        file: file.clone(),
+        pattern: format!(r"(?m)^{}=.*$", regex::escape(key)),
+        // 替换文本中的 `$` 会被当作捕获组引用，需转义
+        insert: format!("{key}={value}").replace('$', "$$"),
+    };
+    if apply_patch(&patch, false, backup, false)? != PatchStatus::AnchorNotFound {
+        return Ok(());
+    }
+    info!("{} not found in {}, appending", key, file);
+    let mut content = fs::read_to_string(ioc_file)?;
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("{key}={value}\n"));
+    fs::write(ioc_file, content)
+}
+
+/// 使用 STM32CubeMX 创建新项目，`run_init` 时返回 init 的结果
+pub fn create_project(opts: CreateOptions) -> anyhow::Result<Option<InitReport>> {
+    let CreateOptions {
+        project_name,
+        toolchain,
+        run_init,
+        hse_value,
+        board,
+        output_dir,
+        ioc_set,
+        allow_dirty,
+        mut init_options,
+    } = opts;
+
+    let re_name = Regex::new(r"^[A-Za-z_][A-Za-z0-9_-]*$").unwrap();
+    if !re_name.is_match(&project_name) {
+        return Err(anyhow!(
+            "Invalid project name `{}`: must start with a letter or underscore \
+             and contain only letters, digits, `_` or `-`",
+            project_name
+        ));
+    }
+    let (mcu, hse_value) = match board {
+        Some(name) => {
+            let Some(board) = find_board(&name) else {
+                let known: Vec<&str> = BOARDS.iter().map(|b| b.name).collect();
+                return Err(anyhow!(
+                    "Unknown board `{}`, known boards: {}",
+                    name,
+                    known.join(", ")
+                ));
+            };
+            info!("Using board {} ({})", board.name, board.mcu);
+            (board.mcu, board.hse_value)
+        }
+        None => (DEFAULT_MCU, hse_value),
+    };
+    if hse_value == 0 {
+        return Err(anyhow!("HSE value must be a positive integer"));
+    }
+    let cubemx_timeout = Duration::from_secs(
+        init_options
+            .cubemx_timeout
+            .unwrap_or(DEFAULT_CUBEMX_TIMEOUT),
+    );
+    let mut ioc_values = vec![("RCC.HSE_VALUE".to_string(), hse_value.to_string())];
+    for (key, value) in ioc_set {
+        ioc_values.retain(|(k, _)| *k != key);
+        ioc_values.push((key, value));
+    }
+
+    let project_dir = output_dir.join(&project_name);
+    if init_options.dry_run {
+        info!(
+            "[dry-run] Would create project directory {}",
+            project_dir.display()
+        );
+        info!(
+            "[dry-run] Would run STM32CubeMX scripts for {} using toolchain {}",
+            mcu,
+            get_toolchain(&toolchain)
+        );
+        for (key, value) in &ioc_values {
+            info!(
+                "[dry-run] Would set {}={} in {}.ioc",
+                key, value, project_name
+            );
+        }
+        if run_init {
+            info!("[dry-run] Would run init process");
+        }
+        return Ok(None);
+    }
+
+    check_stm32cubemx()?;
+    if project_dir.exists() {
+        if has_uncommitted_changes(&project_dir) {
+            if !allow_dirty {
+                return Err(anyhow!(
+                    "{} has uncommitted changes, commit or stash them first, or pass --allow-dirty to delete them",
+                    project_dir.display()
+                ));
+            }
+            warn!(
+                "{} has uncommitted changes, they will be lost",
+                project_dir.display()
+            );
+        }
+        if io::stdin().is_terminal() {
+            let result = Confirm::new()
+                .with_prompt(
+                    "Project already exists. Regenerate? This will delete all existing content.",
+                )
+                .default(false) // false 对应 [y/N] 的 N
+                .interact()?;
+            if !result {
+                info!("Creation aborted!");
+                return Err(anyhow!("Creation aborted!"));
+            }
+        } else if !allow_dirty {
+            return Err(anyhow!(
+                "{} already exists, pass --allow-dirty to regenerate it non-interactively",
+                project_dir.display()
+            ));
+        }
+        fs::remove_dir_all(&project_dir)?;
+    }
+    fs::create_dir_all(&project_dir)?;
+    // STM32CubeMX 的工作目录与本进程相同，脚本中使用绝对路径
+    let absolute_dir = env::current_dir()?.join(&project_dir);
+    let ioc_file = absolute_dir.join(format!("{project_name}.ioc"));
+
+    let ctx = CreateContext {
+        project_name: &project_name,
+        mcu,
+        project_dir: &script_path(&absolute_dir),
+        ioc_file_path: &script_path(&ioc_file),
+        toolchain: get_toolchain(&toolchain),
+        generate_under_root: toolchain == Toolchain::STM32CubeIDE,
+        couple_files: init_options.couple_files,
+    };
+    info!("Using toolchain {}", get_toolchain(&toolchain));
+
+    // 渲染初次运行的脚本
+    let script = render_string("create-project-cmd1", CREATE_PROJECT_CMD1, &ctx)?;
+    info!("Running first script");
+    match run_script(script, cubemx_timeout) {
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to run first script: {}", e);
+            return Err(anyhow::Error::new(e).context("Failed to run first script"));
+        }
+    };
+    info!("Patching .ioc file");
+    for (key, value) in &ioc_values {
+        set_ioc_value(&ioc_file, key, value, init_options.backup)?;
+    }
+    // 渲染第二次运行的脚本
+    let script = render_string("create-project-cmd2", CREATE_PROJECT_CMD2, &ctx)?;
+    info!("Running second script");
+    match run_script(script, cubemx_timeout) {
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to run second script: {}", e);
+            return Err(anyhow::Error::new(e).context("Failed to run second script"));
+        }
+    };
+
+    if run_init {
+        info!("Running init process");
+        init_options.project_dir = project_dir;
+        return Ok(Some(init_project(init_options)?));
+    }
+    Ok(None)
+}
+
+/// 仅根据 Makefile 重新生成 EIDE 配置，覆盖已有文件，不修改 Makefile 与 UserCode
+pub fn regenerate_eide(
+    root: &Path,
+    dry_run: bool,
+    probe: Probe,
+    flasher: Flasher,
+) -> anyhow::Result<InitReport> {
+    if !root.join("Makefile").exists() {
+        return Err(anyhow!("EIDE generation requires `Makefile`"));
+    }
+    let mut report = InitReport {
+        ide: Some(IDEType::Eide),
+        dry_run,
+        ..Default::default()
+    };
+    let existing = top_level_entries(root);
+    eide_custom_init(
+        root,
+        &Config::load(root),
+        true,
+        dry_run,
+        probe,
+        flasher,
+        &mut report,
+    )?;
+    if !dry_run {
+        record_generated(root, &report, &existing)?;
+    }
+    info!("EIDE config regenerated!");
+    Ok(report)
+}
+
+/// 使用工具链的 size 统计 Makefile 构建出的 ELF 文件的 Flash 与 RAM 占用
+pub fn size_report(root: &Path) -> anyhow::Result<SizeReport> {
+    let makefile = fs::read_to_string(root.join("Makefile"))
+        .map_err(|e| anyhow!("Size report requires `Makefile`: {}", e))?;
+    let parsed_makefile = makefile_parser::parse_makefile(&makefile);
+    let target = parsed_makefile
+        .target
+        .as_deref()
+        .ok_or_else(|| anyhow!("TARGET is not set in Makefile"))?;
+    let build_dir = parsed_makefile.build_dir.as_deref().unwrap_or("build");
+    let elf = root_path(
+        root,
+        &format!("{}/{}.elf", build_dir.trim_end_matches('/'), target),
+    );
+    if !Path::new(&elf).is_file() {
+        return Err(anyhow!("{} not found, build the project first", elf));
+    }
+
+    let tool = parsed_makefile.tool("size");
+    let output = match Command::new(&tool).arg(&elf).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Err(anyhow!(
+                "`{}` not found, install the Arm GNU toolchain and add it to PATH or set GCC_PATH",
+                tool
+            ));
+        }
+        Err(e) => return Err(anyhow!("Failed to execute `{}`: {}", tool, e)),
+    };
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`{}` failed with status {}: {}",
+            tool,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // Berkeley 格式：表头之后一行 text data bss dec hex filename
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let values: Vec<u64> = stdout
+        .lines()
+        .nth(1)
+        .unwrap_or_default()
+        .split_whitespace()
+        .take(3)
+        .filter_map(|value| value.parse().ok())
+        .collect();
+    let [text, data, bss] = values[..] else {
+        return Err(anyhow!(
+            "Unexpected output of `{}`: {}",
+            tool,
+            stdout.trim()
+        ));
+    };
+    Ok(SizeReport {
+        elf,
+        text,
+        data,
+        bss,
+    })
+}
+
+/// 检查项目是否已正确初始化，逐项输出结果，返回是否全部通过
+pub fn verify_project(root: &Path) -> anyhow::Result<bool> {
+    let config = Config::load(root);
+    let mut checks: Vec<(String, bool)> = Vec::new();
+
+    for dir in &config.directories {
+        checks.push((format!("directory {dir}"), root.join(dir).is_dir()));
+    }
+
+    let patches = non_intrusive_header_patches(CMakeLists::detect(root), config.app_dir());
+    let mut patched_files = 0;
+    for patch in patches.into_iter().map(|patch| in_dir(patch, root)) {
+        let Patch::Append { file, marker, .. } = patch else {
+            continue;
+        };
+        let Ok(content) = fs::read_to_string(&file) else {
+            continue;
+        };
+        patched_files += 1;
+        checks.push((
+            format!("non-intrusive header in {file}"),
+            content.contains(&marker),
+        ));
+    }
+    if patched_files == 0 {
+        checks.push((
+            "Makefile, CMakeLists_template.txt or CMakeLists.txt".to_string(),
+            false,
+        ));
+    }
+
+    checks.push((
+        ".clang-format".to_string(),
+        root.join(".clang-format").is_file(),
+    ));
+
+    let build_dir = format!("{}/", makefile_build_dir(root).trim_end_matches('/'));
+    let ignored = fs::read_to_string(root.join(".gitignore"))
+        .is_ok_and(|content| content.lines().any(|line| line.trim() == build_dir));
+    checks.push((format!("{build_dir} in .gitignore"), ignored));
+
+    for (name, passed) in &checks {
+        if *passed {
+            info!("[PASS] {}", name);
+        } else {
+            error!("[FAIL] {}", name);
+        }
+    }
+    let failed = checks.iter().filter(|(_, passed)| !passed).count();
+    if failed == 0 {
+        info!("All {} checks passed", checks.len());
+    } else {
+        warn!("{} of {} checks failed", failed, checks.len());
+    }
+    Ok(failed == 0)
+}
+
+/// 清理 init 生成的文件并撤销补丁
+///
+/// 只删除清单中记录且内容未被修改的文件，目录只在清空后删除，被修改的文件与其所在目录会保留
+pub fn clean_project(root: &Path, force: bool) -> anyhow::Result<()> {
+    let config = Config::load(root);
+    let mut manifest = Manifest::load(root);
+    if manifest.is_empty() {
+        warn!(
+            "`{}` not found, keeping generated files and only reverting patches",
+            MANIFEST_FILE
+        );
+    }
+    manifest.files.retain(|p, _| root.join(p).is_file());
+    // .gitattributes 可能在 init 前就已存在，只在与模板一致时删除
+    manifest.files.remove(".gitattributes");
+    let (mut files, mut modified): (Vec<String>, Vec<String>) = manifest
+        .files
+        .keys()
+        .cloned()
+        .partition(|p| manifest.is_unmodified(root, p));
+    if let Ok(content) = fs::read_to_string(root.join(".gitattributes")) {
+        // 模板不含变量
+        if content == render_string(".gitattributes", GITATTRIBUTES, &())? {
+            files.push(".gitattributes".to_string());
+        } else {
+            modified.push(".gitattributes".to_string());
+        }
+    }
+    for path in &modified {
+        warn!("Keeping modified {}", root_path(root, path));
+    }
+
+    if !force {
+        let result = Confirm::new()
+            .with_prompt(format!(
+                "This will remove {} and revert patches. Continue?",
+                if files.is_empty() {
+                    "nothing".to_string()
+                } else {
+                    files.join(", ")
+                }
+            ))
+            .default(false)
+            .interact()?;
+        if !result {
+            info!("Clean aborted!");
+            return Ok(());
+        }
+    }
+
+    for path in files {
+        fs::remove_file(root.join(&path))?;
+        info!("Removed {}", root_path(root, &path));
+        manifest.files.remove(&path);
+    }
+    // 先删除子目录，含有其他文件的目录保留在清单中
+    let dirs: Vec<String> = manifest.dirs.iter().rev().cloned().collect();
+    for dir in dirs {
+        let full_path = root.join(&dir);
+        if !full_path.is_dir() {
+            manifest.dirs.remove(&dir);
+        } else if fs::read_dir(&full_path)?.next().is_none() {
+            fs::remove_dir(&full_path)?;
+            info!("Removed {}", full_path.display());
+            manifest.dirs.remove(&dir);
+        } else {
+            warn!("Keeping non-empty {}", full_path.display());
+        }
+    }
+    manifest.save(root)?;
+
+    for patch in non_intrusive_header_patches(CMakeLists::detect(root), config.app_dir())
+        .into_iter()
+        .chain(clion_patches())
+        .chain(fpu_patches())
+        .chain([cmake_toolchain_patch(
+            installed_toolchain_flags(root).as_deref(),
+        )])
+        .chain(cmake_lists_patches())
+        .chain(config.patches.iter().cloned())
+        .map(|patch| in_dir(patch, root))
+    {
+        if revert_patch(&patch, false, false)? {
+            info!("Reverted patch in {}", get_file(&patch));
+        }
+    }
+
+    info!("STM32 project cleaned!");
+    Ok(())
+}
+
+/// 仅撤销 Makefile 与 CMakeLists_template.txt 中的非侵入式头文件补丁，保留 UserCode
+pub fn remove_non_intrusive_headers(root: &Path) -> anyhow::Result<()> {
+    let config = Config::load(root);
+    let mut removed = false;
+    for patch in non_intrusive_header_patches(CMakeLists::detect(root), config.app_dir()) {
+        let patch = in_dir(patch, root);
+        if revert_patch(&patch, false, false)? {
+            info!("Removed non-intrusive header from {}", get_file(&patch));
+            removed = true;
+        }
+    }
+    if !removed {
+        info!("Non-intrusive header is not installed, nothing to remove");
+    }
+    Ok(())
+}
+
+/// 旧版本存放 app.h/app.c 的目录
+const LEGACY_APP_DIR: &str = "UserCode/app";
+
+/// 将旧版本的目录布局迁移到当前配置，app 目录变化时同时更新非侵入式头文件补丁
+pub fn migrate_project(root: &Path, dry_run: bool, force: bool) -> anyhow::Result<()> {
+    let config = Config::load(root);
+    let app_dir = config.app_dir();
+    let mut moves: Vec<(&str, &str)> = Vec::new();
+    if app_dir != LEGACY_APP_DIR {
+        moves.push((LEGACY_APP_DIR, app_dir));
+    }
+    moves.retain(|(from, to)| {
+        if !root.join(from).is_dir() {
+            return false;
+        }
+        if root.join(to).exists() {
+            warn!("Both {} and {} exist, please merge them manually", from, to);
+            return false;
+        }
+        true
+    });
+    if moves.is_empty() {
+        info!("No legacy layout found, nothing to migrate");
+        return Ok(());
+    }
+
+    let summary = moves
+        .iter()
+        .map(|(from, to)| format!("{from} -> {to}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if !force && !dry_run {
+        let result = Confirm::new()
+            .with_prompt(format!("This will move {}. Continue?", summary))
+            .default(false)
+            .interact()?;
+        if !result {
+            info!("Migrate aborted!");
+            return Ok(());
+        }
+    }
+
+    for (from, to) in &moves {
+        if dry_run {
+            info!("[dry-run] Would move {} to {}", from, to);
+            continue;
+        }
+        if let Some(parent) = root.join(to).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(root.join(from), root.join(to))?;
+        info!("Moved {} to {}", from, to);
+    }
+
+    // 只替换已安装的补丁，未配置过非侵入式头文件的项目保持不变
+    if moves.iter().any(|(from, _)| *from == LEGACY_APP_DIR) {
+        let cmake_lists = CMakeLists::detect(root);
+        for (old, new) in non_intrusive_header_patches(cmake_lists, LEGACY_APP_DIR)
+            .into_iter()
+            .zip(non_intrusive_header_patches(cmake_lists, app_dir))
+        {
+            let (old, new) = (in_dir(old, root), in_dir(new, root));
+            if revert_patch(&old, dry_run, false)?
+                && apply_patch(&new, dry_run, false, false)?.changed()
+                && !dry_run
+            {
+                info!("Updated non-intrusive header in {}", get_file(&new));
+            }
+        }
+    }
+
+    info!("STM32 project migrated!");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toolchain_flags_are_escaped_for_cmake() {
+        let flags = r#"-DNAME=\"foo\" -DPATH=C:\sdk -I${CMAKE_SOURCE_DIR}/libs"#;
+        let Patch::Append { insert, .. } = cmake_toolchain_patch(Some(flags)) else {
+            unreachable!()
+        };
+        assert!(insert.contains(
+            r#"set(STM32_TOOL_FLAGS "-I${CMAKE_SOURCE_DIR}/UserCode -DNAME=\\\"foo\\\" -DPATH=C:\\sdk -I${CMAKE_SOURCE_DIR}/libs")"#
+        ));
+        assert_eq!(cmake_unescape(&cmake_escape(flags)), flags);
+    }
+
+    /// 在临时目录中生成 EIDE 配置并返回解析后的 eide.json
+    fn render_eide(flasher: Flasher) -> serde_json::Value {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(
+            root.path().join("Makefile"),
+            "TARGET = demo\nC_SOURCES = Core/Src/main.c\nASM_SOURCES = startup_stm32f407xx.s\nC_DEFS = -DUSE_HAL_DRIVER -DSTM32F407xx\n",
+        )
+        .unwrap();
+        fs::write(
+            root.path().join("demo.ioc"),
+            "Mcu.CPN=STM32F407VGT6\nMcu.Family=STM32F4\n",
+        )
+        .unwrap();
+        let mut report = InitReport::default();
+        eide_custom_init(
+            root.path(),
+            &Config::default(),
+            false,
+            false,
+            Probe::Stlink,
+            flasher,
+            &mut report,
+        )
+        .unwrap();
+        let content = fs::read_to_string(root.path().join(".eide/eide.json")).unwrap();
+        serde_json::from_str(&content).unwrap()
+    }
+
+    #[test]
+    fn eide_chip_name_in_openocd_uploader() {
+        let eide = render_eide(Flasher::Openocd);
+        assert_eq!(eide["deviceName"], "STM32F407VG");
+        let target = &eide["targets"]["Debug"];
+        assert_eq!(target["uploader"], "OpenOCD");
+        assert_eq!(target["uploadConfig"]["target"], "stm32f4x");
+        assert_eq!(target["uploadConfig"]["cpuInfo"]["cpuName"], "STM32F407VG");
+    }
+
+    #[test]
+    fn eide_chip_name_in_stlink_uploader() {
+        let eide = render_eide(Flasher::Stlink);
+        assert_eq!(eide["deviceName"], "STM32F407VG");
+        let target = &eide["targets"]["Debug"];
+        assert_eq!(target["uploader"], "STLink");
+        assert_eq!(target["uploadConfig"]["proType"], "SWD");
+        assert_eq!(target["uploadConfig"]["cpuInfo"]["cpuName"], "STM32F407VG");
+    }
+
+    #[test]
+    fn clean_keeps_modified_user_code() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(
+            root.path().join("Makefile"),
+            "TARGET = demo\nC_SOURCES = Core/Src/main.c\nC_DEFS = -DUSE_HAL_DRIVER -DSTM32F407xx\n",
+        )
+        .unwrap();
+        fs::create_dir_all(root.path().join("UserCode/legacy")).unwrap();
+        fs::write(root.path().join("UserCode/legacy/old.c"), "").unwrap();
+        init_project(InitOptions {
+            project_dir: root.path().to_path_buf(),
+            skip_git: true,
+            no_openocd: true,
+            author: Some("tester".to_string()),
+            email: Some(String::new()),
+            ..Default::default()
+        })
+        .unwrap();
+        let app_c = root.path().join("UserCode/app/app.c");
+        fs::write(&app_c, "void app_main(void) { /* user code */ }\n").unwrap();
+
+        clean_project(root.path(), true).unwrap();
+        assert!(app_c.is_file());
+        assert!(!root.path().join("UserCode/app/app.h").exists());
+        assert!(!root.path().join("UserCode/bsp").exists());
+        assert!(!root.path().join(".clang-format").exists());
+        assert!(root.path().join("UserCode/legacy/old.c").is_file());
+        assert!(!fs::read_to_string(root.path().join("Makefile"))
+            .unwrap()
+            .contains("app.h"));
+        // 清单只保留未删除的内容，恢复文件后可以再次清理
+        let manifest = Manifest::load(root.path());
+        assert_eq!(
+            manifest.files.keys().collect::<Vec<_>>(),
+            ["UserCode/app/app.c"]
+        );
+    }
+
+    #[test]
+    fn clean_keeps_edited_gitattributes() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join(".gitattributes");
+        fs::write(&path, "*.c text eol=lf\n").unwrap();
+        clean_project(root.path(), true).unwrap();
+        assert!(path.is_file());
+
+        fs::write(
+            &path,
+            render_string(".gitattributes", GITATTRIBUTES, &()).unwrap(),
+        )
+        .unwrap();
+        clean_project(root.path(), true).unwrap();
+        assert!(!path.exists());
+    }
+}
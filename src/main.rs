@@ -1,41 +1,52 @@
-mod contexts;
-mod generate_gitignore;
-mod patches;
-mod render;
-mod stm32cubemx;
-mod templates;
-mod utils;
-
-use crate::contexts::{CreateContext, EIDEConfigContext};
-use crate::generate_gitignore::generate_gitignore;
-use crate::patches::{apply_patch, Patch};
-use crate::render::{render_file, render_string};
-use crate::stm32cubemx::{generate_code, get_toolchain, run_script, Toolchain};
-use crate::templates::{
-    APP_C, APP_H, CLANG_FORMAT, CREATE_PROJECT_CMD1, CREATE_PROJECT_CMD2, EIDE_CONFIG,
-    EIDE_WORKSPACE, README_MD,
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use init_stm32_project::{
+    clean_project, create_project, init_project, migrate_project, regenerate_eide,
+    remove_non_intrusive_headers, size_report, verify_project, ClangFormatStyle, CreateOptions,
+    CubeMxError, FPUType, Flasher, IDEType, InitOptions, InitReport, Probe, SizeReport, Toolchain,
 };
-use crate::utils::get_author;
-use anyhow::anyhow;
-use chrono::Local;
-use clap::{Parser, Subcommand, ValueEnum};
-use dialoguer::{Confirm, Select};
-use serde::Serialize;
-use std::path::Path;
-use std::process::{Command, Stdio};
-use std::{env, fs};
-use tracing::{error, info, warn};
-
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-enum FPUType {
-    Hard,
-    Soft,
+use std::fs;
+use std::path::PathBuf;
+use tracing::Level;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<LogLevel> for Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Trace => Level::TRACE,
+            LogLevel::Debug => Level::DEBUG,
+            LogLevel::Info => Level::INFO,
+            LogLevel::Warn => Level::WARN,
+            LogLevel::Error => Level::ERROR,
+        }
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// 初始化 STM32 项目
-    Init(InitArgs),
+    Init {
+        /// 项目目录，默认为当前目录，如 monorepo 中的 firmware
+        #[arg(long, value_name = "DIR")]
+        path: Option<PathBuf>,
+
+        #[command(flatten)]
+        init_args: InitArgs,
+    },
 
     /// 创建新项目
     Create {
@@ -51,10 +62,99 @@ enum Commands {
         #[arg(long)]
         run_init: bool,
 
+        /// 外部高速晶振频率 (Hz)
+        #[arg(long, default_value_t = 8000000, value_parser = clap::value_parser!(u32).range(1..))]
+        hse: u32,
+
+        /// 开发板名，使用开发板的芯片和晶振频率，如 nucleo-f401re
+        #[arg(long, conflicts_with = "hse")]
+        board: Option<String>,
+
+        /// 在该目录下创建项目目录，默认为当前目录
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+
+        /// 设置 .ioc 中的配置项，可重复，如 --ioc-set PH0-OSC_IN.GPIO_Label=HSE_IN
+        #[arg(long, value_name = "KEY=VALUE", value_parser = parse_ioc_value)]
+        ioc_set: Vec<(String, String)>,
+
+        /// 项目目录已存在时允许删除其中未提交的修改，非交互运行时不询问直接重新生成
+        #[arg(long)]
+        allow_dirty: bool,
+
         /// 使用 init 的参数
         #[command(flatten)]
         init_args: InitArgs,
     },
+
+    /// 清理 init 生成的文件并撤销补丁
+    Clean {
+        /// 项目目录，默认为当前目录，如 monorepo 中的 firmware
+        #[arg(long, value_name = "DIR")]
+        path: Option<PathBuf>,
+        /// 跳过确认
+        #[arg(long)]
+        force: bool,
+        /// 只撤销非侵入式头文件补丁（-include app.h），不删除文件
+        #[arg(long)]
+        headers_only: bool,
+    },
+
+    /// 将旧版本的 UserCode 目录布局迁移到当前配置
+    Migrate {
+        /// 项目目录，默认为当前目录，如 monorepo 中的 firmware
+        #[arg(long, value_name = "DIR")]
+        path: Option<PathBuf>,
+        /// 只输出将要执行的操作，不实际修改文件
+        #[arg(long)]
+        dry_run: bool,
+        /// 跳过确认
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// 仅重新生成 EIDE 配置，会覆盖 eide.json、工作区与 launch.json
+    Eide {
+        /// 项目目录，默认为当前目录，如 monorepo 中的 firmware
+        #[arg(long, value_name = "DIR")]
+        path: Option<PathBuf>,
+        /// 只输出将要执行的操作，不实际修改文件
+        #[arg(long)]
+        dry_run: bool,
+        /// launch.json 使用的调试器
+        #[arg(long, value_enum, default_value_t = Probe::Stlink)]
+        probe: Probe,
+        /// eide.json 中的烧录方式
+        #[arg(long, value_enum, default_value_t = Flasher::Stlink)]
+        flasher: Flasher,
+    },
+
+    /// 检查项目是否已正确初始化，有检查项失败时返回非零退出码
+    Verify {
+        /// 项目目录，默认为当前目录，如 monorepo 中的 firmware
+        #[arg(long, value_name = "DIR")]
+        path: Option<PathBuf>,
+    },
+
+    /// 统计构建出的 ELF 文件的 Flash 与 RAM 占用
+    Size {
+        /// 项目目录，默认为当前目录，如 monorepo 中的 firmware
+        #[arg(long, value_name = "DIR")]
+        path: Option<PathBuf>,
+    },
+
+    /// 解析 Makefile 并以 JSON 输出到 stdout，用于调试解析结果
+    ParseMakefile {
+        /// Makefile 路径
+        #[arg(default_value = "Makefile")]
+        path: PathBuf,
+    },
+
+    /// 生成 shell 补全脚本并输出到 stdout
+    Completions {
+        /// shell 类型：bash、zsh、fish、powershell、elvish
+        shell: Shell,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -65,6 +165,22 @@ struct InitArgs {
     /// 跳过生成 .clang-format
     #[arg(long, default_value_t = false)]
     skip_generate_clang_format: bool,
+    /// .clang-format 的风格，custom 时复制 --template-dir 中的 .clang-format
+    #[arg(long, value_enum, default_value_t = ClangFormatStyle::Default)]
+    clang_format_style: ClangFormatStyle,
+    /// 自定义模板目录
+    #[arg(
+        long,
+        value_name = "DIR",
+        required_if_eq("clang_format_style", "custom")
+    )]
+    template_dir: Option<PathBuf>,
+    /// .gitignore 已存在时合并缺少的规则，而不是跳过
+    #[arg(long)]
+    merge_gitignore: bool,
+    /// 跳过生成 .gitattributes
+    #[arg(long, default_value_t = false)]
+    skip_gitattributes: bool,
     /// 跳过非侵入式头文件配置
     ///
     /// 只有当 skip_generate_user_code 未启用时生效
@@ -74,12 +190,87 @@ struct InitArgs {
         default_value_t = false
     )]
     skip_non_intrusive_headers: bool,
+    /// 修改 Makefile、CMakeLists_template.txt 等已有文件前显示变更并确认
+    #[arg(long)]
+    interactive_patch: bool,
+    /// 生成文件头中的作者，默认读取 git config user.name
+    #[arg(long)]
+    author: Option<String>,
+    /// 生成文件头中的邮箱，默认读取 git config user.email
+    #[arg(long)]
+    email: Option<String>,
     /// 选择 FPU 类型
     #[arg(long, short, default_value = "hard")]
     fpu: FPUType,
     /// 强制重新生成
     #[arg(long)]
     force: bool,
+    /// 选择 IDE，指定后跳过交互式选择
+    ///
+    /// 未指定且 stdin 不是终端时默认为 none
+    #[arg(long)]
+    ide: Option<IDEType>,
+    /// 跳过生成 openocd.cfg
+    #[arg(long)]
+    no_openocd: bool,
+    /// openocd.cfg 与 launch.json 使用的调试器
+    #[arg(long, value_enum, default_value_t = Probe::Stlink)]
+    probe: Probe,
+    /// EIDE 的烧录方式，openocd 时使用 --probe 对应的 interface
+    #[arg(long, value_enum, default_value_t = Flasher::Stlink)]
+    flasher: Flasher,
+    /// 只输出将要执行的操作，不实际修改文件
+    #[arg(long)]
+    dry_run: bool,
+    /// 打补丁前将原文件备份为 <file>.bak，已有备份时不覆盖
+    #[arg(long)]
+    backup: bool,
+    /// 根据 Makefile 生成 .clangd
+    #[arg(long)]
+    clangd: bool,
+    /// 根据 Makefile 生成 VSCode C/C++ 扩展的 .vscode/c_cpp_properties.json
+    #[arg(long)]
+    cpp_properties: bool,
+    /// 生成扫描 UserCode 与 Core 的 Doxyfile，文档输出到 docs 目录
+    #[arg(long)]
+    doxygen: bool,
+    /// 等待 STM32CubeMX 生成代码的超时时间（秒），默认 120
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    cubemx_timeout: Option<u64>,
+    /// STM32CubeMX 生成代码失败时的重试次数，默认 1
+    #[arg(long)]
+    cubemx_retries: Option<u32>,
+    /// STM32CubeMX 是否为每个外设生成单独的 .c/.h 文件，如 --couple-files false
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    couple_files: bool,
+    /// git 初始分支名，如 main，默认使用 git 配置
+    #[arg(long)]
+    branch: Option<String>,
+    /// 跳过 git init 与初始提交，用于已有仓库中的子目录
+    #[arg(long, conflicts_with = "branch")]
+    skip_git: bool,
+    /// 为 Makefile 项目生成 .vscode/tasks.json（编译与烧录任务）
+    #[arg(long)]
+    vscode_tasks: bool,
+    /// 安装检查 clang-format 的 pre-commit hook
+    #[arg(long)]
+    install_hooks: bool,
+    /// 向 cmake/gcc-arm-none-eabi.cmake 追加 UserCode 头文件路径
+    #[arg(long)]
+    patch_toolchain: bool,
+    /// 追加到 CMake 工具链文件的额外编译参数，隐含 --patch-toolchain
+    #[arg(long, allow_hyphen_values = true)]
+    toolchain_flags: Option<String>,
+    /// 根据 .ioc 中启用的外设在 drivers 目录生成驱动桩文件
+    #[arg(long)]
+    scaffold_drivers: bool,
+    /// 只生成指定的 UserCode 目录，以逗号分隔，如 bsp,drivers，app 目录始终生成
+    #[arg(
+        long,
+        value_delimiter = ',',
+        conflicts_with = "skip_generate_user_code"
+    )]
+    dirs: Option<Vec<String>>,
 }
 
 #[derive(Parser)]
@@ -88,318 +279,236 @@ struct InitArgs {
 struct Cli {
     #[command(subcommand)]
     command: Commands,
-}
 
-#[derive(Serialize)]
-struct InitContext {
-    author: String,
-    date: String,
-    year: String,
-}
+    /// 输出格式，json 时日志仅输出警告到 stderr，结束后向 stdout 输出执行结果
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
 
-fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
-    let cli = Cli::parse();
+    /// 只输出错误日志
+    #[arg(long, short, global = true, conflicts_with = "log_level")]
+    quiet: bool,
 
-    match cli.command {
-        Commands::Init(args) => {
-            run_init(
-                args.skip_generate_user_code,
-                args.skip_generate_clang_format,
-                args.skip_non_intrusive_headers,
-                args.fpu,
-                args.force,
-            )?;
-        }
-        Commands::Create {
-            project_name,
-            toolchain,
-            run_init,
-            init_args,
-        } => {
-            run_create(project_name, toolchain, run_init, init_args)?;
-        }
-    }
-
-    Ok(())
+    /// 日志级别，指定后优先于 RUST_LOG
+    #[arg(long, global = true, value_enum)]
+    log_level: Option<LogLevel>,
 }
 
-fn run_init(
-    skip_generate_user_code: bool,
-    skip_generate_clang_format: bool,
-    skip_non_intrusive_headers: bool,
-    fpu: FPUType,
-    force: bool,
-) -> std::io::Result<()> {
-    // 渲染上下文
-    let author = get_author();
-
-    let now = Local::now();
-    let ctx = InitContext {
-        author,
-        date: now.format("%Y-%m-%d").to_string(),
-        year: now.format("%Y").to_string(),
-    };
-
-    // 初始化项目配置
-    info!("Initializing git repository...");
-    let status = Command::new("git")
-        .arg("init")
-        .stdout(Stdio::null()) // 屏蔽 stdout
-        .stderr(Stdio::null()) // 屏蔽 stderr
-        .status();
-    match status {
-        Ok(status) if status.success() => {
-            info!("Git repository initialized successfully!");
-        }
-        Ok(status) => {
-            error!("Git init failed with status: {}", status);
-        }
-        Err(e) => {
-            error!("Failed to execute git: {}", e);
+impl From<InitArgs> for InitOptions {
+    fn from(args: InitArgs) -> Self {
+        InitOptions {
+            project_dir: PathBuf::new(),
+            skip_generate_user_code: args.skip_generate_user_code,
+            skip_generate_clang_format: args.skip_generate_clang_format,
+            clang_format_style: args.clang_format_style,
+            template_dir: args.template_dir,
+            merge_gitignore: args.merge_gitignore,
+            skip_gitattributes: args.skip_gitattributes,
+            skip_non_intrusive_headers: args.skip_non_intrusive_headers,
+            interactive_patch: args.interactive_patch,
+            author: args.author,
+            email: args.email,
+            fpu: args.fpu,
+            force: args.force,
+            ide: args.ide,
+            no_openocd: args.no_openocd,
+            probe: args.probe,
+            flasher: args.flasher,
+            dry_run: args.dry_run,
+            backup: args.backup,
+            clangd: args.clangd,
+            cpp_properties: args.cpp_properties,
+            doxygen: args.doxygen,
+            cubemx_timeout: args.cubemx_timeout,
+            cubemx_retries: args.cubemx_retries,
+            couple_files: args.couple_files,
+            branch: args.branch,
+            skip_git: args.skip_git,
+            vscode_tasks: args.vscode_tasks,
+            install_hooks: args.install_hooks,
+            patch_toolchain: args.patch_toolchain,
+            toolchain_flags: args.toolchain_flags,
+            scaffold_drivers: args.scaffold_drivers,
+            dirs: args.dirs,
         }
     }
-    info!("Generating .gitignore file...");
-    generate_gitignore(None, force)?;
-
-    if !skip_generate_clang_format {
-        info!("Generating .clang-format file");
-        render_file(".clang-format", CLANG_FORMAT, &ctx, force)?;
-    }
-
-    if !skip_generate_user_code {
-        info!("Generating user code directories...");
-        let directories: Vec<&str> = vec![
-            "UserCode/bsp",
-            "UserCode/drivers",
-            "UserCode/third_party",
-            "UserCode/libs",
-            "UserCode/interfaces",
-            "UserCode/controllers",
-            "UserCode/app",
-        ];
-        for dir in directories {
-            fs::create_dir_all(dir)?;
-            info!("Created dir {}", dir);
-        }
-        render_file("UserCode/app/app.h", APP_H, &ctx, force)?;
-        render_file("UserCode/app/app.c", APP_C, &ctx, force)?;
-        render_file("UserCode/README.md", README_MD, &ctx, force)?;
-    }
+}
 
-    if !skip_non_intrusive_headers {
-        if skip_generate_user_code {
-            info!("Skipping non-intrusive headers due to skip_generate_user_code");
-        } else {
-            info!("Generating non-intrusive headers");
-            apply_patch(
-                &Patch::Append {
-                    file: "CMakeLists_template.txt".to_string(),
-                    after: "add_executable".to_string(),
-                    insert: "\n# 非侵入式引入头文件\ntarget_compile_options(${PROJECT_NAME}.elf PRIVATE -include ${CMAKE_SOURCE_DIR}/UserCode/app/app.h)\n".to_string(),
-                    marker: "UserCode/app/app.h".to_string(),
-                })?;
-            apply_patch(&Patch::Append {
-                file: "Makefile".to_string(),
-                after: "CFLAGS += $(MCU)".to_string(),
-                insert: "\n# 非侵入式引入头文件\nCFLAGS += -include UserCode/app/app.h\n"
-                    .to_string(),
-                marker: "UserCode/app/app.h".to_string(),
-            })?;
+/// 解析 --ioc-set 的 KEY=VALUE
+fn parse_ioc_value(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
         }
+        _ => Err(format!("expected KEY=VALUE, got `{arg}`")),
     }
+}
 
-    if Path::new("CMakeLists_template.txt").exists() {
-        info!("Found `CMakeLists_template.txt`, initializing CLion project...");
-        clion_custom_init(fpu)?;
+fn print_size_report(format: OutputFormat, report: &SizeReport) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+        return Ok(());
     }
-    if Path::new("Makefile").exists() {
-        info!("Found `Makefile`, initializing Makefile project...");
-        let choice = Select::new()
-            .with_prompt("Choose your ide")
-            .item("VSCode + EIDE")
-            .item("None")
-            .default(0)
-            .interact()?;
-        match choice {
-            0_usize => eide_custom_init(force)?,
-            1_usize => {
-                warn!("--");
-            }
-            2_usize.. => todo!(),
-        }
-    }
-
-    info!("STM32 project initialized!");
+    let kib = |bytes: u64| bytes as f64 / 1024.0;
+    println!("{}", report.elf);
+    println!(
+        "Flash: {:>8} B ({:.1} KiB) = text {} + data {}",
+        report.flash(),
+        kib(report.flash()),
+        report.text,
+        report.data
+    );
+    println!(
+        "RAM:   {:>8} B ({:.1} KiB) = data {} + bss {}",
+        report.ram(),
+        kib(report.ram()),
+        report.data,
+        report.bss
+    );
     Ok(())
 }
 
-#[derive(Serialize)]
-struct EIDEProjectFile<'a> {
-    path: &'a String,
+fn print_report(format: OutputFormat, report: &InitReport) -> anyhow::Result<()> {
+    if format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(report)?);
+    }
+    Ok(())
 }
 
-fn eide_custom_init(force: bool) -> std::io::Result<()> {
-    let makefile = fs::read_to_string("Makefile")?;
-    let parsed_makefile = makefile_parser::parse_makefile(makefile.as_str());
-
-    let mut files = Vec::with_capacity(parsed_makefile.asm_sources.len());
-    for source in parsed_makefile.asm_sources.iter() {
-        files.push(EIDEProjectFile { path: source });
+/// 初始化日志，--quiet/--log-level 优先，json 输出时默认只输出警告到 stderr
+fn init_tracing(cli: &Cli) {
+    let level = if cli.quiet {
+        Some(Level::ERROR)
+    } else {
+        cli.log_level.map(Level::from)
+    };
+    let json = cli.format == OutputFormat::Json;
+    if level.is_none() && !json {
+        tracing_subscriber::fmt::init();
+        return;
+    }
+    let builder = tracing_subscriber::fmt().with_max_level(level.unwrap_or(Level::WARN));
+    if json {
+        builder.with_writer(std::io::stderr).init();
+    } else {
+        builder.init();
     }
+}
 
-    let project_name = parsed_makefile.target.unwrap_or("".to_string());
-
-    // list dir
-    let mut src = Vec::new();
-    let path = Path::new(".");
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            if let Some(name) = path.file_name() {
-                if let Some(name_str) = name.to_str() {
-                    if !name_str.starts_with('.') {
-                        src.push(name_str.to_string());
-                    }
-                }
-            }
+/// 针对 STM32CubeMX 错误给出处理建议
+fn cubemx_hint(e: &CubeMxError) -> &'static str {
+    match e {
+        CubeMxError::NotFound => {
+            "install STM32CubeMX and add `stm32cubemx` to PATH, or set STM32CUBEMX_BIN \
+             (STM32CUBEMX_PATH on Windows)"
+        }
+        CubeMxError::MultipleIoc => {
+            "keep only one .ioc file in the project, or run in a terminal to choose one"
+        }
+        CubeMxError::NoIoc => "run in the directory containing the .ioc file",
+        CubeMxError::NonZeroExit(_) => {
+            "set STM32CUBEMX_KEEP_SCRIPT=1 to keep the generated script for debugging"
         }
+        CubeMxError::Spawn(_) => "check that STM32CUBEMX_BIN points to an executable",
+        CubeMxError::Timeout(_) => "increase the timeout with --cubemx-timeout",
+        CubeMxError::Io(_) => "check the permissions of the project directory",
     }
-    let mut includes = parsed_makefile.includes;
-    includes.push("UserCode".to_string());
-
-    let ctx = EIDEConfigContext {
-        project_name: &project_name,
-        ld_file_path: &parsed_makefile.ldscript.unwrap_or_default(),
-        src_dirs: &serde_json::to_string(&src)?,
-        include_list: &serde_json::to_string(&includes)?,
-        define_list: &serde_json::to_string(&parsed_makefile.defines)?,
-        src_files: &serde_json::to_string(&files)?,
-    };
+}
 
-    info!("Generating EIDE config file...");
-    render_file(".eide/eide.json", EIDE_CONFIG, &ctx, force)?;
-    info!("Generating EIDE workspace file...");
-    render_file(
-        format!("{project_name}.code-workspace").as_str(),
-        EIDE_WORKSPACE,
-        &ctx,
-        force,
-    )?;
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    init_tracing(&cli);
 
-    Ok(())
+    let result = run(cli);
+    if let Err(e) = &result
+        && let Some(e) = e.downcast_ref::<CubeMxError>()
+    {
+        eprintln!("hint: {}", cubemx_hint(e));
+    }
+    result
 }
 
-fn clion_custom_init(fpu: FPUType) -> std::io::Result<()> {
-    apply_patch(&Patch::Replace {
-        file: "CMakeLists_template.txt".to_string(),
-        find: "include_directories(${includes})".to_string(),
-        insert: "include_directories(${includes} UserCode)".to_string(),
-    })?;
-    apply_patch(&Patch::Replace {
-        file: "CMakeLists_template.txt".to_string(),
-        find: "file(GLOB_RECURSE SOURCES ${sources})".to_string(),
-        insert: "file(GLOB_RECURSE SOURCES ${sources} \"UserCode/*.*\")".to_string(),
-    })?;
-    match fpu {
-        FPUType::Hard => apply_patch(&Patch::RegexReplace {
-            file: "CMakeLists_template.txt".to_string(),
-            pattern: "(?ms)^#Uncomment for hardware floating point(?:\n#.*?)*\n?(?:\n|$)"
-                .to_string(),
-            insert: "${0/#/}".to_string(),
-        }),
-        FPUType::Soft => apply_patch(&Patch::RegexReplace {
-            file: "CMakeLists_template.txt".to_string(),
-            pattern: "(?ms)^#Uncomment for hardware floating point(?:\n#.*?)*\n?(?:\n|$)"
-                .to_string(),
-            insert: "${0/#/}".to_string(),
-        }),
-    }?;
-    info!("Try to regenerate code(using STM32CubeMX)...");
-    match generate_code(Some(Toolchain::STM32CubeIDE)) {
-        Ok(_) => {
-            info!("Regenerate code successfully!")
+fn run(cli: Cli) -> anyhow::Result<()> {
+    match cli.command {
+        Commands::Init { path, init_args } => {
+            let report = init_project(InitOptions {
+                project_dir: path.unwrap_or_default(),
+                ..init_args.into()
+            })?;
+            print_report(cli.format, &report)?;
         }
-        Err(_) => {
-            warn!("Regenerate code failed, please regenerate code manually!");
+        Commands::Create {
+            project_name,
+            toolchain,
+            run_init,
+            hse,
+            board,
+            output_dir,
+            ioc_set,
+            allow_dirty,
+            init_args,
+        } => {
+            let report = create_project(CreateOptions {
+                project_name,
+                toolchain,
+                run_init,
+                hse_value: hse,
+                board,
+                output_dir: output_dir.unwrap_or_default(),
+                ioc_set,
+                allow_dirty,
+                init_options: init_args.into(),
+            })?;
+            if let Some(report) = report {
+                print_report(cli.format, &report)?;
+            }
         }
-    };
-    Ok(())
-}
-
-fn run_create(
-    project_name: String,
-    toolchain: Toolchain,
-    run_init_: bool,
-    init_args: InitArgs,
-) -> anyhow::Result<()> {
-    let path = Path::new(&project_name);
-    if path.exists() {
-        let result = Confirm::new()
-            .with_prompt(
-                "Project already exists. Regenerate? This will delete all existing content.",
-            )
-            .default(false) // false 对应 [y/N] 的 N
-            .interact()?;
-        if !result {
-            info!("Creation aborted!");
-            return Err(anyhow!("Creation aborted!"));
+        Commands::Clean {
+            path,
+            headers_only: true,
+            ..
+        } => {
+            remove_non_intrusive_headers(&path.unwrap_or_default())?;
         }
-        fs::remove_dir_all(path)?;
-    }
-    fs::create_dir_all(&project_name)?;
-    env::set_current_dir(&project_name)?;
-    let current_dir = env::current_dir()?;
-
-    let ctx = CreateContext {
-        project_name: &project_name,
-        project_dir: &current_dir.to_string_lossy().to_string(),
-        ioc_file_path: &current_dir
-            .join(format!("{project_name}.ioc"))
-            .to_string_lossy()
-            .to_string(),
-        toolchain: get_toolchain(&toolchain),
-        generate_under_root: toolchain == Toolchain::STM32CubeIDE,
-    };
-    info!("Using toolchain {}", get_toolchain(&toolchain));
-
-    // 渲染初次运行的脚本
-    let script = render_string(CREATE_PROJECT_CMD1, &ctx)?;
-    info!("Running first script");
-    match run_script(script) {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Failed to run first script: {}", e);
-            return Err(anyhow!("Failed to run first script: {}", e));
+        Commands::Clean { path, force, .. } => {
+            clean_project(&path.unwrap_or_default(), force)?;
         }
-    };
-    info!("Patching .ioc file");
-    apply_patch(&Patch::RegexReplace {
-        file: format!("{project_name}.ioc"),
-        pattern: r"RCC\.HSE_VALUE=(\d+)".to_string(),
-        insert: "RCC.HSE_VALUE=8000000".to_string(),
-    })?;
-    // 渲染第二次运行的脚本
-    let script = render_string(CREATE_PROJECT_CMD2, &ctx)?;
-    info!("Running second script");
-    match run_script(script) {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Failed to run second script: {}", e);
-            return Err(anyhow!("Failed to run second script: {}", e));
+        Commands::Migrate {
+            path,
+            dry_run,
+            force,
+        } => {
+            migrate_project(&path.unwrap_or_default(), dry_run, force)?;
+        }
+        Commands::Eide {
+            path,
+            dry_run,
+            probe,
+            flasher,
+        } => {
+            let report = regenerate_eide(&path.unwrap_or_default(), dry_run, probe, flasher)?;
+            print_report(cli.format, &report)?;
+        }
+        Commands::Verify { path } => {
+            if !verify_project(&path.unwrap_or_default())? {
+                anyhow::bail!("Project verification failed");
+            }
+        }
+        Commands::Size { path } => {
+            let report = size_report(&path.unwrap_or_default())?;
+            print_size_report(cli.format, &report)?;
+        }
+        Commands::ParseMakefile { path } => {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+            let config = makefile_parser::parse_makefile(&content);
+            println!("{}", serde_json::to_string_pretty(&config)?);
+        }
+        Commands::Completions { shell } => {
+            // 补全脚本需绑定实际安装的可执行文件名
+            let bin_name = env!("CARGO_BIN_NAME");
+            clap_complete::generate(shell, &mut Cli::command(), bin_name, &mut std::io::stdout());
         }
-    };
-
-    if run_init_ {
-        info!("Running init process");
-        run_init(
-            init_args.skip_generate_user_code,
-            init_args.skip_generate_clang_format,
-            init_args.skip_non_intrusive_headers,
-            init_args.fpu,
-            init_args.force,
-        )?;
     }
+
     Ok(())
 }
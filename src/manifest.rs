@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::warn;
+
+/// 记录 init 生成的文件与目录，clean 据此只删除工具生成且未被修改的内容
+pub const MANIFEST_FILE: &str = ".stm32-tool-manifest.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Manifest {
+    /// 生成的文件，相对于项目根目录，值为生成时内容的摘要
+    pub files: BTreeMap<String, String>,
+    /// 生成的目录，相对于项目根目录
+    pub dirs: BTreeSet<String>,
+}
+
+/// 文件内容的 FNV-1a 摘要，只用于判断文件是否被修改
+pub fn digest(content: &[u8]) -> String {
+    let hash = content.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}")
+}
+
+impl Manifest {
+    /// 读取项目根目录下的清单，不存在或解析失败时为空
+    pub fn load(root: &Path) -> Manifest {
+        let Ok(content) = fs::read_to_string(root.join(MANIFEST_FILE)) else {
+            return Manifest::default();
+        };
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            warn!("Invalid {}, ignoring: {}", MANIFEST_FILE, e);
+            Manifest::default()
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.dirs.is_empty()
+    }
+
+    /// 记录生成的文件或目录，路径相对于项目根目录，文件记录当前内容的摘要
+    pub fn record(&mut self, root: &Path, path: &str) {
+        let full_path = root.join(path);
+        if full_path.is_dir() {
+            self.dirs.insert(path.to_string());
+        } else if let Ok(content) = fs::read(&full_path) {
+            self.files.insert(path.to_string(), digest(&content));
+        }
+    }
+
+    /// 文件是否仍是生成时的内容
+    pub fn is_unmodified(&self, root: &Path, path: &str) -> bool {
+        self.files.get(path).is_some_and(|expected| {
+            fs::read(root.join(path)).is_ok_and(|content| digest(&content) == *expected)
+        })
+    }
+
+    /// 写回清单，清单为空时删除清单文件
+    pub fn save(&self, root: &Path) -> io::Result<()> {
+        let path = root.join(MANIFEST_FILE);
+        if self.is_empty() {
+            return match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let content = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(&path, content + "\n")
+    }
+}
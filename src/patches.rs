@@ -1,27 +1,70 @@
-use regex::Regex;
+use dialoguer::Confirm;
+use regex::{Captures, Regex};
 use serde::Deserialize;
 use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
 
-#[derive(Debug, Deserialize)]
+use crate::utils::{changed_lines, log_dry_run_diff};
+
+#[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "mode")]
 pub enum Patch {
     #[serde(rename = "append")]
     Append { file: String, after: String, insert: String, marker: String },
+    #[serde(rename = "prepend")]
+    Prepend { file: String, before: String, insert: String, marker: String },
     #[serde(rename = "replace")]
     Replace { file: String, find: String, insert: String },
     #[serde(rename = "regex_replace")]
     RegexReplace { file: String, pattern: String, insert: String },
+    /// 在正则匹配处插入内容，保留匹配文本，`insert` 支持 `$1` 等捕获组引用
+    #[serde(rename = "regex_insert")]
+    RegexInsert { file: String, pattern: String, insert: String, position: InsertPosition, marker: String },
+    /// 取消注释 header 行之后连续的注释行，header 的匹配规则见 `is_uncomment_header`
+    #[serde(rename = "uncomment")]
+    Uncomment { file: String, header: String },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InsertPosition {
+    Before,
+    After,
 }
 
-pub fn apply_patch(patch: &Patch) -> std::io::Result<()> {
-    let content = match fs::read_to_string(&get_file(patch)) {
+/// 应用补丁的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchStatus {
+    /// 文件已被修改（dry-run 时为将会修改）
+    Applied,
+    /// 补丁已存在，无需修改
+    AlreadyPresent,
+    /// 找不到插入或替换的位置，模板可能已变化
+    AnchorNotFound,
+    /// 文件不存在
+    FileMissing,
+    /// 用户拒绝了修改
+    Declined,
+}
+
+impl PatchStatus {
+    pub fn changed(self) -> bool {
+        self == PatchStatus::Applied
+    }
+}
+
+/// 应用补丁，`confirm` 为 true 时写入前显示变更并询问
+pub fn apply_patch(patch: &Patch, dry_run: bool, backup: bool, confirm: bool) -> std::io::Result<PatchStatus> {
+    let (content, eol) = match read_normalized(get_file(patch)) {
         Ok(c) => c,
-        Err(_) => return Ok(()), // 文件不存在，跳过
+        Err(_) => return Ok(PatchStatus::FileMissing),
     };
 
     let new_content = match patch {
         Patch::Append { after, insert, marker, .. } => {
-            if content.contains(marker) { return Ok(()); }
+            if content.contains(marker) { return Ok(PatchStatus::AlreadyPresent); }
+            if !content.lines().any(|line| line.contains(after)) { return Ok(PatchStatus::AnchorNotFound); }
             content
                 .lines()
                 .map(|line| {
@@ -34,27 +77,363 @@ pub fn apply_patch(patch: &Patch) -> std::io::Result<()> {
                 .collect::<Vec<_>>()
                 .join("\n") + "\n"
         }
+        Patch::Prepend { before, insert, marker, .. } => {
+            if content.contains(marker) { return Ok(PatchStatus::AlreadyPresent); }
+            if !content.lines().any(|line| line.contains(before)) { return Ok(PatchStatus::AnchorNotFound); }
+            let mut inserted = false;
+            content
+                .lines()
+                .map(|line| {
+                    if !inserted && line.contains(before) {
+                        inserted = true;
+                        format!("{}\n{}", insert, line)
+                    } else {
+                        line.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n") + "\n"
+        }
         Patch::Replace { find, insert, .. } => {
-            if content.contains(insert) { return Ok(()); }
+            if content.contains(insert) { return Ok(PatchStatus::AlreadyPresent); }
+            if !content.contains(find) { return Ok(PatchStatus::AnchorNotFound); }
             content.replace(find, insert)
         }
         Patch::RegexReplace { pattern, insert, .. } => {
-            let re = Regex::new(pattern).unwrap();
-            if re.is_match(&content) && content.contains(insert) {
-                return Ok(());
-            }
+            let re = compile_regex(pattern, get_file(patch))?;
+            if !re.is_match(&content) { return Ok(PatchStatus::AnchorNotFound); }
+            if content.contains(insert) { return Ok(PatchStatus::AlreadyPresent); }
             re.replace_all(&content, insert.as_str()).to_string()
         }
+        Patch::RegexInsert { pattern, insert, position, marker, .. } => {
+            if content.contains(marker) { return Ok(PatchStatus::AlreadyPresent); }
+            let re = compile_regex(pattern, get_file(patch))?;
+            if !re.is_match(&content) { return Ok(PatchStatus::AnchorNotFound); }
+            re.replace_all(&content, |caps: &Captures| {
+                let mut expanded = String::new();
+                caps.expand(insert, &mut expanded);
+                match position {
+                    InsertPosition::Before => expanded + &caps[0],
+                    InsertPosition::After => caps[0].to_string() + &expanded,
+                }
+            })
+            .to_string()
+        }
+        Patch::Uncomment { header, .. } => {
+            if !content.lines().any(|line| is_uncomment_header(line, header)) {
+                return Ok(PatchStatus::AnchorNotFound);
+            }
+            let mut in_block = false;
+            let mut changed = false;
+            let new_content = content
+                .lines()
+                .map(|line| {
+                    if is_uncomment_header(line, header) {
+                        in_block = true;
+                        line.to_string()
+                    } else if in_block && line.starts_with('#') {
+                        changed = true;
+                        line[1..].to_string()
+                    } else {
+                        in_block = in_block && !line.trim().is_empty();
+                        line.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n") + "\n";
+            if !changed { return Ok(PatchStatus::AlreadyPresent); }
+            new_content
+        }
+    };
+    if new_content == content { return Ok(PatchStatus::AlreadyPresent); }
+
+    if dry_run {
+        log_dry_run_diff(get_file(patch), &content, &new_content);
+        return Ok(PatchStatus::Applied);
+    }
+    if confirm && !confirm_patch(get_file(patch), &content, &new_content)? {
+        info!("Skipped patching {}", get_file(patch));
+        return Ok(PatchStatus::Declined);
+    }
+    if backup {
+        backup_file(get_file(patch))?;
+    }
+    fs::write(get_file(patch), restore_line_endings(new_content, eol))?;
+    Ok(PatchStatus::Applied)
+}
+
+/// 显示将被修改的行并询问是否写入
+fn confirm_patch(file: &str, old: &str, new: &str) -> std::io::Result<bool> {
+    let (removed, added) = changed_lines(old, new);
+    eprintln!("Changes to {}:", file);
+    for line in removed {
+        eprintln!("  - {}", line);
+    }
+    for line in added {
+        eprintln!("  + {}", line);
+    }
+    Confirm::new()
+        .with_prompt(format!("Apply changes to {}?", file))
+        .default(true)
+        .interact()
+        .map_err(std::io::Error::other)
+}
+
+/// 编译补丁中的正则，出错时返回包含模式与文件名的错误，而不是 panic
+fn compile_regex(pattern: &str, file: &str) -> std::io::Result<Regex> {
+    Regex::new(pattern).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput,
+        format!("Invalid regex `{}` in patch for {}: {}", pattern, file, e)))
+}
+
+/// 撤销补丁，返回文件是否被修改
+///
+/// `Append`/`Prepend` 会移除插入的内容块，`Replace` 会把 `insert` 还原为 `find`，
+/// `RegexInsert` 会移除紧挨匹配处的插入内容，`Uncomment` 会重新注释，`RegexReplace` 无法撤销
+pub fn revert_patch(patch: &Patch, dry_run: bool, backup: bool) -> std::io::Result<bool> {
+    let (content, eol) = match read_normalized(get_file(patch)) {
+        Ok(c) => c,
+        Err(_) => return Ok(false), // 文件不存在，跳过
+    };
+
+    let new_content = match patch {
+        Patch::Append { insert, marker, .. } | Patch::Prepend { insert, marker, .. } => {
+            if !content.contains(marker) { return Ok(false); }
+            let lines: Vec<&str> = content.lines().collect();
+            let block: Vec<&str> = insert.split('\n').collect();
+            let Some(start) = lines.windows(block.len()).position(|w| w == block.as_slice()) else {
+                warn!("Marker `{}` found in {}, but the inserted block was modified",
+                    marker, get_file(patch));
+                return Ok(false);
+            };
+            let mut lines = lines;
+            lines.drain(start..start + block.len());
+            lines.join("\n") + "\n"
+        }
+        Patch::Replace { find, insert, .. } => {
+            if !content.contains(insert) { return Ok(false); }
+            content.replace(insert, find)
+        }
+        Patch::RegexReplace { .. } => {
+            warn!("Regex patch in {} can not be reverted", get_file(patch));
+            return Ok(false);
+        }
+        Patch::RegexInsert { pattern, insert, position, marker, .. } => {
+            if !content.contains(marker) { return Ok(false); }
+            let re = compile_regex(pattern, get_file(patch))?;
+            let mut result = String::with_capacity(content.len());
+            let mut last = 0;
+            for caps in re.captures_iter(&content) {
+                let m = caps.get(0).unwrap();
+                let mut expanded = String::new();
+                caps.expand(insert, &mut expanded);
+                // 跳过紧挨着匹配文本的插入内容
+                let (keep_end, skip_to) = match position {
+                    InsertPosition::Before if content[last..m.start()].ends_with(&expanded) => {
+                        (m.start() - expanded.len(), m.start())
+                    }
+                    InsertPosition::After if content[m.end()..].starts_with(&expanded) => {
+                        (m.end(), m.end() + expanded.len())
+                    }
+                    _ => continue,
+                };
+                result.push_str(&content[last..keep_end]);
+                last = skip_to;
+            }
+            result.push_str(&content[last..]);
+            if result == content { return Ok(false); }
+            result
+        }
+        Patch::Uncomment { header, .. } => {
+            let mut in_block = false;
+            let mut changed = false;
+            let new_content = content
+                .lines()
+                .map(|line| {
+                    if is_uncomment_header(line, header) {
+                        in_block = true;
+                        line.to_string()
+                    } else if in_block && !line.trim().is_empty() {
+                        if line.starts_with('#') { return line.to_string(); }
+                        changed = true;
+                        format!("#{}", line)
+                    } else {
+                        in_block = false;
+                        line.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n") + "\n";
+            if !changed { return Ok(false); }
+            new_content
+        }
     };
 
-    fs::write(get_file(patch), new_content)?;
+    if dry_run {
+        log_dry_run_diff(get_file(patch), &content, &new_content);
+        return Ok(true);
+    }
+    if backup {
+        backup_file(get_file(patch))?;
+    }
+    fs::write(get_file(patch), restore_line_endings(new_content, eol))?;
+    Ok(true)
+}
+
+/// 读取文件并统一为 LF 换行，同时返回文件中占多数的换行符
+///
+/// 补丁都按 LF 处理，写回时再用 `restore_line_endings` 还原，避免 CRLF 文件整体被改写
+fn read_normalized(file: &str) -> std::io::Result<(String, &'static str)> {
+    let content = fs::read_to_string(file)?;
+    let crlf = content.matches("\r\n").count();
+    let lf = content.matches('\n').count() - crlf;
+    if crlf > lf {
+        Ok((content.replace("\r\n", "\n"), "\r\n"))
+    } else {
+        Ok((content, "\n"))
+    }
+}
+
+fn restore_line_endings(content: String, eol: &str) -> String {
+    if eol == "\n" { content } else { content.replace('\n', eol) }
+}
+
+/// 判断是否为 `Uncomment` 的 header 行
+///
+/// 忽略 `#` 后的空白和大小写，并允许 header 之后还有其他内容，
+/// 以兼容不同版本 CubeMX 模板的措辞，如 `# Uncomment for hardware floating point unit`
+fn is_uncomment_header(line: &str, header: &str) -> bool {
+    fn normalize(s: &str) -> String {
+        s.trim().trim_start_matches('#').split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+    }
+    let (line, header) = (normalize(line), normalize(header));
+    !header.is_empty() && line.starts_with(&header)
+}
+
+/// 修改前备份为 `<file>.bak`，已有备份时保留，确保其为最初的内容
+fn backup_file(file: &str) -> std::io::Result<()> {
+    let backup = format!("{file}.bak");
+    if Path::new(&backup).exists() {
+        return Ok(());
+    }
+    fs::copy(file, &backup)?;
+    info!("Backed up {} to {}", file, backup);
     Ok(())
 }
 
-fn get_file(patch: &Patch) -> &str {
+/// 将补丁的文件路径改为相对于 `root`
+pub fn in_dir(mut patch: Patch, root: &Path) -> Patch {
+    let (Patch::Append { file, .. } | Patch::Prepend { file, .. } | Patch::Replace { file, .. }
+    | Patch::RegexReplace { file, .. } | Patch::RegexInsert { file, .. } | Patch::Uncomment { file, .. }) = &mut patch;
+    *file = root.join(&*file).to_string_lossy().to_string();
+    patch
+}
+
+pub fn get_file(patch: &Patch) -> &str {
     match patch {
         Patch::Append { file, .. } => file,
+        Patch::Prepend { file, .. } => file,
         Patch::Replace { file, .. } => file,
         Patch::RegexReplace { file, .. } => file,
+        Patch::RegexInsert { file, .. } => file,
+        Patch::Uncomment { file, .. } => file,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FPU_TEMPLATE: &str = "\
+include_directories(${includes})
+
+#Uncomment for hardware floating point
+#add_compile_definitions(ARM_MATH_CM4;ARM_MATH_MATRIX_CHECK;ARM_MATH_ROUNDING)
+#add_compile_options(-mfloat-abi=hard -mfpu=fpv4-sp-d16)
+#add_link_options(-mfloat-abi=hard -mfpu=fpv4-sp-d16)
+
+#Uncomment for software floating point
+#add_compile_options(-mfloat-abi=soft)
+
+add_executable(${PROJECT_NAME}.elf ${SOURCES} ${LINKER_SCRIPT})
+";
+
+    /// 在临时目录中创建内容为 `content` 的 CMakeLists_template.txt，应用取消注释补丁后返回文件内容
+    fn uncomment(content: &str, header: &str) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("CMakeLists_template.txt");
+        fs::write(&file, content).unwrap();
+        let patch = Patch::Uncomment {
+            file: file.to_string_lossy().to_string(),
+            header: header.to_string(),
+        };
+        assert_eq!(apply_patch(&patch, false, false, false).unwrap(), PatchStatus::Applied);
+        fs::read_to_string(&file).unwrap()
+    }
+
+    #[test]
+    fn hard_and_soft_fpu_produce_different_templates() {
+        let hard = uncomment(FPU_TEMPLATE, "#Uncomment for hardware floating point");
+        let soft = uncomment(FPU_TEMPLATE, "#Uncomment for software floating point");
+        assert_ne!(hard, soft);
+
+        assert!(hard.contains("\nadd_compile_options(-mfloat-abi=hard -mfpu=fpv4-sp-d16)\n"));
+        assert!(hard.contains("\n#add_compile_options(-mfloat-abi=soft)\n"));
+        assert!(soft.contains("\nadd_compile_options(-mfloat-abi=soft)\n"));
+        assert!(soft.contains("\n#add_compile_options(-mfloat-abi=hard -mfpu=fpv4-sp-d16)\n"));
+    }
+
+    #[test]
+    fn uncomment_header_matches_cubemx_wordings() {
+        let header = "#Uncomment for hardware floating point";
+        for line in [
+            "#Uncomment for hardware floating point",
+            "# Uncomment for hardware floating point unit",
+            "#uncomment for Hardware  floating point",
+            "  #Uncomment for hardware floating point\t",
+        ] {
+            assert!(is_uncomment_header(line, header), "{line}");
+        }
+        for line in [
+            "#Uncomment for software floating point",
+            "#add_compile_options(-mfloat-abi=hard)",
+            "",
+        ] {
+            assert!(!is_uncomment_header(line, header), "{line}");
+        }
+        assert!(!is_uncomment_header("#anything", "#"));
+    }
+
+    #[test]
+    fn uncomment_newer_cubemx_template() {
+        let template = FPU_TEMPLATE.replace(
+            "#Uncomment for hardware floating point",
+            "# Uncomment for hardware floating point unit",
+        );
+        let hard = uncomment(&template, "#Uncomment for hardware floating point");
+        assert!(hard.contains("\nadd_compile_options(-mfloat-abi=hard -mfpu=fpv4-sp-d16)\n"));
+    }
+
+    #[test]
+    fn crlf_file_keeps_crlf_after_patching() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("Makefile");
+        fs::write(&file, "C_SOURCES = main.c\r\nCFLAGS = -Wall\r\n").unwrap();
+        let patch = Patch::Append {
+            file: file.to_string_lossy().to_string(),
+            after: "C_SOURCES".to_string(),
+            insert: "# stm32-tool\nC_SOURCES += app.c".to_string(),
+            marker: "# stm32-tool".to_string(),
+        };
+
+        assert_eq!(apply_patch(&patch, false, false, false).unwrap(), PatchStatus::Applied);
+        let content = fs::read_to_string(&file).unwrap();
+        assert_eq!(
+            content,
+            "C_SOURCES = main.c\r\n# stm32-tool\r\nC_SOURCES += app.c\r\nCFLAGS = -Wall\r\n"
+        );
+        assert_eq!(apply_patch(&patch, false, false, false).unwrap(), PatchStatus::AlreadyPresent);
+
+        assert!(revert_patch(&patch, false, false).unwrap());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "C_SOURCES = main.c\r\nCFLAGS = -Wall\r\n");
     }
 }
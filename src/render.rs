@@ -1,37 +1,104 @@
 use serde::Serialize;
 use std::fs;
+use std::io::Write;
 use std::path::Path;
 use tinytemplate::TinyTemplate;
-use tracing::warn;
+use tracing::{info, warn};
 
+/// 渲染模板并写入文件，返回是否写入（dry-run 时为是否将会写入）
+///
+/// 已有文件的内容与渲染结果相同时不写入，避免 `force` 下更新修改时间导致不必要的重新构建
 pub fn render_file<T: Serialize>(
     path: &str,
     template: &str,
     ctx: &T,
     force: bool,
-) -> std::io::Result<()> {
+    dry_run: bool,
+) -> std::io::Result<bool> {
     if Path::new(path).exists() && !force {
         warn!("Skip existing {}", path);
-        return Ok(());
+        return Ok(false);
+    }
+
+    // 渲染模板
+    let content = render_string(path, template, ctx)?;
+    write_file(path, &content, force, dry_run)
+}
+
+/// 写入不经过模板渲染的内容，跳过与覆盖的规则与 `render_file` 相同
+pub fn write_file(path: &str, content: &str, force: bool, dry_run: bool) -> std::io::Result<bool> {
+    let exists = Path::new(path).exists();
+    if exists && !force {
+        warn!("Skip existing {}", path);
+        return Ok(false);
+    }
+
+    if exists && fs::read(path).is_ok_and(|existing| existing == content.as_bytes()) {
+        info!("Unchanged {}", path);
+        return Ok(false);
+    }
+
+    if dry_run {
+        let action = if exists { "overwrite" } else { "create" };
+        info!(
+            "[dry-run] Would {} {} ({} lines)",
+            action,
+            path,
+            content.lines().count()
+        );
+        return Ok(true);
     }
 
     if let Some(parent) = Path::new(path).parent() {
         fs::create_dir_all(parent)?;
     }
 
-    // 渲染模板
-    let content = render_string(template, ctx)?;
+    write_atomic(Path::new(path), content.as_bytes())?;
+    if exists {
+        info!("Updated {}", path);
+    }
+    Ok(true)
+}
+
+/// 先写入同目录下的临时文件再重命名覆盖目标，避免中断时留下写了一半的文件
+pub fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid file path: {}", path.display()),
+        )
+    })?;
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
 
-    fs::write(path, content)?;
-    Ok(())
+    let result = fs::File::create(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(content)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
 }
 
-pub fn render_string<T: Serialize>(template: &str, ctx: &T) -> std::io::Result<String> {
+/// 渲染模板，`name` 用于在出错时指明是哪个模板
+pub fn render_string<T: Serialize>(name: &str, template: &str, ctx: &T) -> std::io::Result<String> {
     let mut tt = TinyTemplate::new();
-    tt.add_template("tpl", template).unwrap();
+    tt.add_template(name, template).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid template for {}: {}", name, e),
+        )
+    })?;
 
     // 渲染模板
-    let content = tt.render("tpl", ctx).unwrap();
+    let content = tt.render(name, ctx).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Failed to render template for {}: {}", name, e),
+        )
+    })?;
 
     Ok(content)
 }
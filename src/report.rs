@@ -0,0 +1,55 @@
+use crate::IDEType;
+use serde::Serialize;
+
+/// init 的执行结果，用于结构化输出
+#[derive(Debug, Default, Serialize)]
+pub struct InitReport {
+    /// 生成的文件和目录
+    pub created: Vec<String>,
+    /// 被修改的已有文件
+    pub patched: Vec<String>,
+    /// 已存在而跳过的文件
+    pub skipped: Vec<String>,
+    /// 选择的 IDE，未进入 IDE 初始化时为 null
+    pub ide: Option<IDEType>,
+    pub dry_run: bool,
+}
+
+/// 固件的 Flash 与 RAM 占用，来自 `size` 的 Berkeley 格式输出
+#[derive(Debug, Serialize)]
+pub struct SizeReport {
+    pub elf: String,
+    pub text: u64,
+    pub data: u64,
+    pub bss: u64,
+}
+
+impl SizeReport {
+    /// Flash 占用：代码、只读数据与 .data 的初始值
+    pub fn flash(&self) -> u64 {
+        self.text + self.data
+    }
+
+    /// RAM 静态占用，不含运行时的栈与堆
+    pub fn ram(&self) -> u64 {
+        self.data + self.bss
+    }
+}
+
+impl InitReport {
+    /// 记录 `render_file` 的结果
+    pub fn file(&mut self, path: &str, written: bool) {
+        if written {
+            self.created.push(path.to_string());
+        } else {
+            self.skipped.push(path.to_string());
+        }
+    }
+
+    /// 记录被修改的文件，同一文件只记录一次
+    pub fn patch(&mut self, path: &str, changed: bool) {
+        if changed && !self.patched.iter().any(|p| p == path) {
+            self.patched.push(path.to_string());
+        }
+    }
+}
@@ -1,14 +1,67 @@
-use anyhow::Result;
 use clap::ValueEnum;
+use dialoguer::Select;
 use rand::distr::Alphanumeric;
 use rand::{rng, Rng};
 use std::cmp::PartialEq;
-use std::fmt::Write;
+use std::fmt;
 use std::fs::{remove_file, File};
-use std::io::Write as IoWrite;
-use std::process::{Command, Stdio};
+use std::io::{self, IsTerminal, Read, Write as IoWrite};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::{env, fs};
-use tracing::{error, warn};
+use tracing::{error, info, warn, Level};
+
+/// 调用 STM32CubeMX 失败的原因
+#[derive(Debug)]
+pub enum CubeMxError {
+    /// 找不到 STM32CubeMX
+    NotFound,
+    /// 项目中有多个 .ioc 文件，且无法交互选择
+    MultipleIoc,
+    /// 项目中没有 .ioc 文件
+    NoIoc,
+    /// 退出状态非零，被信号结束时为 -1
+    NonZeroExit(i32),
+    /// 无法启动 STM32CubeMX
+    Spawn(io::Error),
+    /// 超过等待时间，已结束进程
+    Timeout(Duration),
+    /// 读写脚本文件或交互选择失败
+    Io(io::Error),
+}
+
+impl fmt::Display for CubeMxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CubeMxError::NotFound => write!(f, "STM32CubeMX not found"),
+            CubeMxError::MultipleIoc => write!(f, "multiple .ioc files found"),
+            CubeMxError::NoIoc => write!(f, "no .ioc file found"),
+            CubeMxError::NonZeroExit(code) => write!(f, "STM32CubeMX exited with status {code}"),
+            CubeMxError::Spawn(e) => write!(f, "failed to execute STM32CubeMX: {e}"),
+            CubeMxError::Timeout(timeout) => {
+                write!(f, "STM32CubeMX timed out after {}s", timeout.as_secs())
+            }
+            CubeMxError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for CubeMxError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CubeMxError::Spawn(e) | CubeMxError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CubeMxError {
+    fn from(e: io::Error) -> Self {
+        CubeMxError::Io(e)
+    }
+}
 
 fn generate_random_string(length: usize) -> String {
     let mut rng = rng();
@@ -18,24 +71,73 @@ fn generate_random_string(length: usize) -> String {
         .collect()
 }
 
-fn get_ioc_files() -> Vec<String> {
-    let mut ioc_files: Vec<String> = Vec::new();
-    let current_dir = std::env::current_dir().expect("Failed to get current directory");
-    if let Ok(entries) = fs::read_dir(current_dir) {
-        for entry in entries {
-            if let Ok(entry) = entry {
-                let path = entry.path();
-                if let Some(extension) = path.extension() {
-                    if extension == "ioc" {
-                        ioc_files.push(path.to_str().unwrap().to_string());
-                    }
-                }
+/// 查找项目根目录下的 .ioc 文件，返回的路径以 `root` 开头
+fn get_ioc_files(root: &Path) -> Vec<PathBuf> {
+    let mut ioc_files: Vec<PathBuf> = Vec::new();
+    let root = if root.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        root
+    };
+    if let Ok(entries) = fs::read_dir(root) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Some(extension) = path.extension()
+                && extension == "ioc"
+            {
+                ioc_files.push(path);
             }
         }
     }
+    ioc_files.sort();
     ioc_files
 }
 
+/// 读取 .ioc 文件中的配置项，如 Mcu.Family
+pub fn get_ioc_value(root: &Path, key: &str) -> Option<String> {
+    let ioc_files = get_ioc_files(root);
+    let content = fs::read_to_string(ioc_files.first()?).ok()?;
+    content.lines().find_map(|line| {
+        let (k, v) = line.split_once('=')?;
+        (k.trim() == key).then(|| v.trim().to_string())
+    })
+}
+
+/// 读取 .ioc 中启用的外设，即 Mcu.IP0、Mcu.IP1 等配置项的值，按序号排列
+pub fn get_ioc_peripherals(root: &Path) -> Vec<String> {
+    let ioc_files = get_ioc_files(root);
+    let Some(content) = ioc_files.first().and_then(|f| fs::read_to_string(f).ok()) else {
+        return Vec::new();
+    };
+    let mut ips: Vec<(usize, String)> = content
+        .lines()
+        .filter_map(|line| {
+            let (k, v) = line.split_once('=')?;
+            // Mcu.IPNb 是外设数量，不是序号
+            let index = k.trim().strip_prefix("Mcu.IP")?.parse().ok()?;
+            Some((index, v.trim().to_string()))
+        })
+        .collect();
+    ips.sort();
+    ips.into_iter().map(|(_, ip)| ip).collect()
+}
+
+/// 读取 .ioc 中的芯片型号，优先使用完整型号 Mcu.CPN，如 STM32F407VGT6
+pub fn get_ioc_part_number(root: &Path) -> Option<String> {
+    get_ioc_value(root, "Mcu.CPN").or_else(|| get_ioc_value(root, "Mcu.Family"))
+}
+
+/// 根据型号前缀判断芯片是否带 FPU，无法识别的系列返回 None
+pub fn has_fpu(part_number: &str) -> Option<bool> {
+    let family = part_number.get(5..7)?.to_ascii_uppercase();
+    match family.as_str() {
+        // Cortex-M0/M0+/M3，以及不带 FPU 的 Cortex-M4 (WL)
+        "C0" | "F0" | "F1" | "F2" | "G0" | "L0" | "L1" | "U0" | "WL" => Some(false),
+        "F3" | "F4" | "F7" | "G4" | "H5" | "H7" | "L4" | "L5" | "U5" | "WB" => Some(true),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum Toolchain {
     /// EWARM V8.32
@@ -63,6 +165,21 @@ pub enum Toolchain {
     CMake,
 }
 
+impl Toolchain {
+    /// 读取 .ioc 中 ProjectManager.TargetToolchain 记录的工具链，未记录或无法识别时返回 None
+    pub fn from_ioc(root: &Path) -> Option<Toolchain> {
+        let value = get_ioc_value(root, "ProjectManager.TargetToolchain")?;
+        let toolchain = Toolchain::value_variants()
+            .iter()
+            .find(|t| get_toolchain(t) == value)
+            .copied();
+        if toolchain.is_none() {
+            warn!("Unknown toolchain `{}` in .ioc", value);
+        }
+        toolchain
+    }
+}
+
 pub fn get_toolchain(toolchain: &Toolchain) -> &'static str {
     match toolchain {
         Toolchain::EwarmV832 => "EWARM V8.32",
@@ -78,79 +195,321 @@ pub fn get_toolchain(toolchain: &Toolchain) -> &'static str {
     }
 }
 
-pub fn generate_code(toolchain: Option<Toolchain>) -> Result<()> {
-    let ioc_files = get_ioc_files();
-    if ioc_files.len() != 1 {
-        warn!("No ioc file is provided or multiple ioc files are provided.");
-        return Err(anyhow::anyhow!(
-            "No ioc file is provided or multiple ioc files are provided."
-        ));
+/// 使用 STM32CubeMX 重新生成代码，退出状态非零时最多重试 `retries` 次
+///
+/// `couple_files` 为 true 时每个外设生成单独的 .c/.h 文件，否则集中生成在 main.c 中
+pub fn generate_code(
+    root: &Path,
+    toolchain: Option<Toolchain>,
+    couple_files: bool,
+    timeout: Duration,
+    retries: u32,
+) -> Result<(), CubeMxError> {
+    let ioc_files = get_ioc_files(root);
+    let ioc_file = match ioc_files.len() {
+        0 => {
+            warn!("No ioc file is provided.");
+            return Err(CubeMxError::NoIoc);
+        }
+        1 => &ioc_files[0],
+        _ if !io::stdin().is_terminal() => {
+            warn!("Multiple ioc files are provided.");
+            return Err(CubeMxError::MultipleIoc);
+        }
+        _ => {
+            let choice = Select::new()
+                .with_prompt("Multiple ioc files found, choose one")
+                .items(ioc_files.iter().map(|f| f.display()))
+                .default(0)
+                .interact()
+                .map_err(|dialoguer::Error::IO(e)| CubeMxError::Io(e))?;
+            &ioc_files[choice]
+        }
+    };
+    if ioc_file.to_str().is_none() {
+        warn!(
+            "Path of {} is not valid UTF-8, STM32CubeMX may fail to load it",
+            ioc_file.display()
+        );
     }
-    let ioc_file = ioc_files.first().unwrap();
-    let mut script = String::new();
-    write!(script, "config load {}\n", ioc_file)?;
+    // STM32CubeMX 需要绝对路径，加引号以支持带空格的路径
+    let ioc_file = std::path::absolute(ioc_file)?;
+    let mut script = format!("config load \"{}\"\n", script_path(&ioc_file));
     if let Some(toolchain) = toolchain {
-        write!(
-            script,
+        script.push_str(&format!(
             "project toolchain \"{}\"\n",
             get_toolchain(&toolchain)
-        )?;
+        ));
         if let Toolchain::STM32CubeIDE = toolchain {
             // Generate Under Root on
-            write!(script, "project generateunderroot 1")?;
+            script.push_str("project generateunderroot 1\n");
         }
     }
     // Generate peripheral initialization as a pair of '.c/.h' files per peripheral
-    write!(script, "project couplefilesbyip 1\n")?;
-    write!(script, "project generate\n")?;
-    write!(script, "exit")?;
+    script.push_str(&format!(
+        "project couplefilesbyip {}\n",
+        u8::from(couple_files)
+    ));
+    script.push_str("project generate\n");
+    script.push_str("exit");
+
+    let mut attempt = 0;
+    loop {
+        match run_script(script.clone(), timeout) {
+            // 未找到程序或超时时重试没有意义
+            Err(CubeMxError::NonZeroExit(_)) if attempt < retries => {
+                attempt += 1;
+                warn!("Retrying STM32CubeMX ({}/{})...", attempt, retries);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// 获取 stm32cubemx 可执行文件路径
+///
+/// 默认使用 PATH 中的 `stm32cubemx`，可通过环境变量 STM32CUBEMX_BIN 覆盖
+fn get_stm32cubemx_bin() -> String {
+    env::var("STM32CUBEMX_BIN").unwrap_or_else(|_| "stm32cubemx".to_string())
+}
+
+/// STM32CubeMX 的启动命令，Windows 下找不到安装位置时为 None
+fn stm32cubemx_command() -> Option<Command> {
+    if cfg!(target_os = "windows") {
+        find_windows_stm32cubemx()
+    } else {
+        Some(Command::new(get_stm32cubemx_bin()))
+    }
+}
+
+/// 检查 STM32CubeMX 是否可用，不可用时返回包含安装指引的错误
+pub fn check_stm32cubemx() -> Result<(), CubeMxError> {
+    let found = stm32cubemx_command().is_some_and(|command| {
+        let program = PathBuf::from(command.get_program());
+        // 带路径时直接检查文件，否则在 PATH 中查找
+        if program.components().count() > 1 {
+            return program.is_file();
+        }
+        env::var_os("PATH")
+            .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(&program).is_file()))
+    });
+    if found {
+        return Ok(());
+    }
+    error!(
+        "STM32CubeMX not found. Install it from https://www.st.com/en/development-tools/stm32cubemx.html, \
+         then add `stm32cubemx` to PATH or set STM32CUBEMX_BIN \
+         (STM32CUBEMX_PATH to the installation directory on Windows)."
+    );
+    Err(CubeMxError::NotFound)
+}
+
+/// Windows 下 STM32CubeMX 的常见安装位置
+const WINDOWS_STM32CUBEMX_DIRS: [&str; 2] = [
+    r"C:\Program Files\STMicroelectronics\STM32Cube\STM32CubeMX",
+    r"C:\Program Files (x86)\STMicroelectronics\STM32Cube\STM32CubeMX",
+];
+
+/// 查找 Windows 下 STM32CubeMX 的启动命令
+///
+/// 依次尝试环境变量 STM32CUBEMX_PATH、STM32CubeMX_dir 和常见安装位置，
+/// 环境变量既可以是安装目录，也可以直接是可执行文件
+fn find_windows_stm32cubemx() -> Option<Command> {
+    let mut candidates: Vec<PathBuf> = ["STM32CUBEMX_PATH", "STM32CubeMX_dir"]
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .map(PathBuf::from)
+        .collect();
+    candidates.extend(WINDOWS_STM32CUBEMX_DIRS.iter().map(PathBuf::from));
 
-    run_script(script)
+    for candidate in candidates {
+        if candidate.is_file() {
+            return Some(Command::new(candidate));
+        }
+        let java = candidate.join("jre").join("bin").join("java.exe");
+        for jar in ["STM32CubeMX.jar", "STM32CubeMX.exe"] {
+            let jar = candidate.join(jar);
+            if java.is_file() && jar.is_file() {
+                let mut command = Command::new(&java);
+                command.arg("-jar").arg(jar);
+                return Some(command);
+            }
+        }
+        let exe = candidate.join("STM32CubeMX.exe");
+        if exe.is_file() {
+            return Some(Command::new(exe));
+        }
+    }
+    None
 }
 
-pub fn run_script(script: String) -> Result<()> {
+/// 等待 STM32CubeMX 执行的默认超时时间（秒）
+pub const DEFAULT_CUBEMX_TIMEOUT: u64 = 120;
+
+/// STM32CubeMX 生成代码失败后的默认重试次数，首次启动时偶尔会失败
+pub const DEFAULT_CUBEMX_RETRIES: u32 = 1;
+
+/// 运行命令并收集输出，超时后结束子进程
+fn output_with_timeout(command: &mut Command, timeout: Duration) -> io::Result<Output> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // 在独立线程中读取输出，避免管道写满导致子进程阻塞
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+
+    let deadline = Instant::now() + timeout;
+    let mut spinner = Spinner::new();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        spinner.tick();
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("timed out after {}s", timeout.as_secs()),
+            ));
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    let join = |handle: Option<thread::JoinHandle<Vec<u8>>>| {
+        handle.and_then(|h| h.join().ok()).unwrap_or_default()
+    };
+    Ok(Output {
+        status,
+        stdout: join(stdout),
+        stderr: join(stderr),
+    })
+}
+
+/// 等待 STM32CubeMX 期间在 stderr 显示的动画，drop 时清除
+///
+/// stderr 不是终端或未输出 info 日志（如 --quiet、--format json）时不显示，避免混入其他输出
+struct Spinner {
+    enabled: bool,
+    start: Instant,
+    frame: usize,
+}
+
+impl Spinner {
+    const FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+    fn new() -> Self {
+        Spinner {
+            enabled: io::stderr().is_terminal() && tracing::enabled!(Level::INFO),
+            start: Instant::now(),
+            frame: 0,
+        }
+    }
+
+    fn tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        let mut stderr = io::stderr();
+        let _ = write!(
+            stderr,
+            "\r{} Waiting for STM32CubeMX... {}s",
+            Self::FRAMES[self.frame % Self::FRAMES.len()],
+            self.start.elapsed().as_secs()
+        );
+        let _ = stderr.flush();
+        self.frame += 1;
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if self.enabled && self.frame > 0 {
+            let mut stderr = io::stderr();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+        }
+    }
+}
+
+fn read_in_background<R: Read + Send + 'static>(mut reader: R) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+/// Windows 下脚本中的路径统一使用 `/`，STM32CubeMX 同样接受，避免 `\` 被当作转义字符
+pub fn script_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if cfg!(target_os = "windows") {
+        path.replace('\\', "/")
+    } else {
+        path.to_string()
+    }
+}
+
+/// 将脚本写入临时文件，以 `-s <script> -q` 直接启动 STM32CubeMX 执行
+///
+/// 不经过 `sh -c` 或 `cmd /C`，Windows 与 Unix 下行为一致
+pub fn run_script(script: String, timeout: Duration) -> Result<(), CubeMxError> {
     let tmp_path = format!("./tmp-script-{}", generate_random_string(8));
     let mut temp_script_file = File::create_new(&tmp_path)?;
     temp_script_file.write_all(script.as_bytes())?;
-    let status = if cfg!(target_os = "windows") {
-        // return Err(anyhow::anyhow!("not support windows"));
-        let dir = match env::var("STM32CubeMX_dir") {
-            Ok(dir) => dir,
-            Err(_) => {
-                error!(
-                    "Environment variable STM32CubeMX_dir is not set. Please configure the STM32CubeMX installation path."
-                );
-                return Err(anyhow::anyhow!(
-                    "Missing environment variable: STM32CubeMX_dir"
-                ));
-            }
-        };
-        Command::new("cmd")
-            .args([
-                "/C",
-                format!("{dir}\\jre\\bin\\java.exe -jar {dir}\\STM32CubeMX.exe -s {tmp_path} -q")
-                    .as_str(),
-            ])
-            .status()
-    } else {
-        Command::new("stm32cubemx")
-            .arg("-s")
-            .arg(&tmp_path)
-            .stdout(Stdio::null()) // 屏蔽 stdout
-            .stderr(Stdio::null()) // 屏蔽 stderr
-            .arg("-q")
-            .status()
+    let mut command = stm32cubemx_command();
+    let program = command
+        .as_ref()
+        .map(|c| c.get_program().to_string_lossy().to_string())
+        .unwrap_or_else(|| "STM32CubeMX".to_string());
+    // 捕获输出，仅在失败时打印，成功时保持安静
+    let output = match command.as_mut() {
+        Some(command) => output_with_timeout(command.arg("-s").arg(&tmp_path).arg("-q"), timeout),
+        None => {
+            error!(
+                "STM32CubeMX not found. Please set STM32CUBEMX_PATH to the STM32CubeMX installation path."
+            );
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "STM32CubeMX installation not found",
+            ))
+        }
     };
-    remove_file(tmp_path)?;
-    match status {
-        Ok(status) if status.success() => Ok(()),
-        Ok(status) => {
-            error!("Run script failed with status: {}", status);
-            Err(anyhow::anyhow!("Run script failed with status: {}", status))
+    // 设置 STM32CUBEMX_KEEP_SCRIPT 时保留脚本用于排查，失败时同时输出脚本内容
+    if env::var_os("STM32CUBEMX_KEEP_SCRIPT").is_some() {
+        info!("Kept STM32CubeMX script at {}", tmp_path);
+        if !matches!(&output, Ok(output) if output.status.success()) {
+            error!("Script content:\n{}", script.trim_end());
+        }
+    } else {
+        remove_file(tmp_path)?;
+    }
+    match output {
+        Ok(output) if output.status.success() => Ok(()),
+        Ok(output) => {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stdout.trim().is_empty() {
+                error!("`{}` stdout:\n{}", program, stdout.trim_end());
+            }
+            if !stderr.trim().is_empty() {
+                error!("`{}` stderr:\n{}", program, stderr.trim_end());
+            }
+            error!("Run script failed with status: {}", output.status);
+            Err(CubeMxError::NonZeroExit(output.status.code().unwrap_or(-1)))
+        }
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+            error!("`{}` {}, killed", program, e);
+            Err(CubeMxError::Timeout(timeout))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            error!("Failed to execute `{}`: {}", program, e);
+            Err(CubeMxError::NotFound)
         }
         Err(e) => {
-            error!("Failed to execute stm32cubemx: {}", e);
-            Err(anyhow::anyhow!("Failed to execute stm32cubemx: {}", e))
+            error!("Failed to execute `{}`: {}", program, e);
+            Err(CubeMxError::Spawn(e))
         }
     }
 }
@@ -1,11 +1,25 @@
 pub const APP_H: &str = include_str!("templates/app.h.tmpl");
 pub const APP_C: &str = include_str!("templates/app.c.tmpl");
 pub const README_MD: &str = include_str!("templates/README.md.tmpl");
+pub const DRIVER_H: &str = include_str!("templates/driver.h.tmpl");
+pub const DRIVER_C: &str = include_str!("templates/driver.c.tmpl");
 
 pub const CLANG_FORMAT: &str = include_str!("templates/clang-format.tmpl");
+/// 仅指定 BasedOnStyle 的 .clang-format
+pub const CLANG_FORMAT_BASED_ON: &str = include_str!("templates/clang-format-based-on.tmpl");
+/// 不经过模板渲染，直接写入或追加到已有的 hook
+pub const PRE_COMMIT_HOOK: &str = include_str!("templates/pre-commit.tmpl");
+pub const GITATTRIBUTES: &str = include_str!("templates/gitattributes.tmpl");
+pub const CLANGD: &str = include_str!("templates/clangd.tmpl");
+pub const C_CPP_PROPERTIES: &str = include_str!("templates/c_cpp_properties.tmpl");
 
 pub const CREATE_PROJECT_CMD1: &str = include_str!("templates/create-project-cmd1.tmpl");
 pub const CREATE_PROJECT_CMD2: &str = include_str!("templates/create-project-cmd2.tmpl");
 
 pub const EIDE_CONFIG: &str = include_str!("templates/eide-config.tmpl");
 pub const EIDE_WORKSPACE: &str = include_str!("templates/eide-workspace.tmpl");
+pub const VSCODE_LAUNCH: &str = include_str!("templates/vscode-launch.tmpl");
+pub const VSCODE_TASKS: &str = include_str!("templates/vscode-tasks.tmpl");
+
+pub const OPENOCD_CFG: &str = include_str!("templates/openocd.cfg.tmpl");
+pub const DOXYFILE: &str = include_str!("templates/Doxyfile.tmpl");
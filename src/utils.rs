@@ -1,4 +1,6 @@
+use std::collections::HashSet;
 use std::process::Command;
+use tracing::info;
 
 pub fn get_author() -> String {
     Command::new("git")
@@ -9,4 +11,43 @@ pub fn get_author() -> String {
         .unwrap_or_else(|| "unknown".into())
         .trim()
         .to_string()
-}
\ No newline at end of file
+}
+
+/// 读取 git 配置中的邮箱，未设置时为空
+pub fn get_email() -> String {
+    Command::new("git")
+        .args(["config", "user.email"])
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+/// 返回被删除和新增的行，忽略空行的变化
+pub fn changed_lines<'a>(old: &'a str, new: &'a str) -> (Vec<&'a str>, Vec<&'a str>) {
+    let old_lines: HashSet<&str> = old.lines().collect();
+    let new_lines: HashSet<&str> = new.lines().collect();
+    let removed = old
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !new_lines.contains(l))
+        .collect();
+    let added = new
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !old_lines.contains(l))
+        .collect();
+    (removed, added)
+}
+
+/// dry-run 模式下输出文件变更摘要
+pub fn log_dry_run_diff(path: &str, old: &str, new: &str) {
+    let (removed, added) = changed_lines(old, new);
+    info!("[dry-run] Would patch {}", path);
+    for line in removed {
+        info!("[dry-run]   - {}", line);
+    }
+    for line in added {
+        info!("[dry-run]   + {}", line);
+    }
+}